@@ -1,35 +1,905 @@
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use lazy_static::lazy_static;
+use rand::distributions::{Alphanumeric, DistString};
 use reqwest::Error;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
 
 use crate::credentials::Credentials;
+use crate::error::AuthFlowError;
 
-#[allow(dead_code)] // Ignore unused fields in the response
+/// Base URL of Twitch's OAuth2 endpoints
+pub const TWITCH_AUTH_URL: &str = "https://id.twitch.tv/oauth2/";
+
+// Access tokens are renewed this long before they expire
+pub(crate) const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+// Added to the polling interval every time the server answers slow_down
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+
+const STATE_LENGTH: usize = 32;
+// Longest redirect request read by the local listener
+const MAX_REQUEST_LENGTH: usize = 8192;
+// Time a browser gets to send its request to the local listener
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time the user gets to authorize the app in `AuthorizationCodeFlow::run`
+pub const DEFAULT_AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Tokens returned when refreshing or exchanging an authorization code
+#[derive(Deserialize)]
+pub struct RefreshTokenResponse {
+    pub access_token: String,
+    // Seconds until the access token expires
+    pub expires_in: u32,
+    // Twitch may rotate the refresh token, the old one stops working then
+    pub refresh_token: String,
+    pub scope: Vec<String>,
+    pub token_type: String,
+}
+
+/// App access token returned by the client credentials grant
 #[derive(Deserialize)]
-struct RefreshTokenResponse {
-    access_token: String,
-    expires_in: u32,
-    refresh_token: String,
-    scope: Vec<String>,
-    token_type: String,
-}
-
-/// Makes an OAuth2 request to get a new access token from the refresh
-/// token. Refresh tokens are longer lived than access tokens, so the
-/// client can be configured once without having to add a new token
-/// constantly.
-pub async fn refresh_access_token(credentials: &Credentials) -> Result<String, Error> {
-    let res = reqwest::Client::new()
-        .post("https://id.twitch.tv/oauth2/token")
-        .form(&[
+pub struct AppTokenResponse {
+    pub access_token: String,
+    // Seconds until the access token expires
+    pub expires_in: u32,
+    pub token_type: String,
+}
+
+/// What Twitch knows about an access token, returned by `validate_token`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenValidation {
+    pub client_id: String,
+    // Not set for app access tokens
+    pub login: Option<String>,
+    pub user_id: Option<String>,
+    #[serde(deserialize_with = "null_as_empty")]
+    pub scopes: Vec<String>,
+    // Seconds until the access token expires
+    pub expires_in: u32,
+}
+
+/// Code the user enters at the verification URI to authorize a device
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    // Seconds until the device code expires
+    pub expires_in: u32,
+    // Seconds to wait between polls for the token
+    pub interval: u32,
+    pub user_code: String,
+    pub verification_uri: String,
+}
+
+// Body of the error responses of the token endpoint
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    message: String,
+}
+
+// Twitch sends null instead of an empty list for tokens without scopes
+fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(Option::<Vec<String>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Makes requests to the OAuth2 endpoints, sharing one HTTP client. The
+/// base URL can be changed to use a different server, e.g. in tests.
+#[derive(Clone)]
+pub struct AuthClient {
+    http: reqwest::Client,
+    base_url: Url,
+}
+
+impl Default for AuthClient {
+    fn default() -> Self {
+        AuthClient::new(Url::parse(TWITCH_AUTH_URL).unwrap())
+    }
+}
+
+impl AuthClient {
+    /// Endpoints are resolved relative to the base URL, so it needs a
+    /// trailing slash
+    pub fn new(base_url: Url) -> Self {
+        AuthClient {
+            http: reqwest::Client::new(),
+            base_url,
+        }
+    }
+
+    fn endpoint(&self, name: &str) -> Url {
+        self.base_url
+            .join(name)
+            .expect("endpoint names are valid relative URLs")
+    }
+
+    /// Makes an OAuth2 request to get a new access token from the refresh
+    /// token. Refresh tokens are longer lived than access tokens, so the
+    /// client can be configured once without having to add a new token
+    /// constantly.
+    pub async fn refresh_access_token(
+        &self,
+        credentials: &Credentials,
+    ) -> Result<RefreshTokenResponse, Error> {
+        let mut form = vec![
             ("grant_type", "refresh_token"),
-            ("refresh_token", &credentials.refresh_token),
-            ("client_id", &credentials.client_id),
-            ("client_secret", &credentials.client_secret),
+            ("refresh_token", credentials.refresh_token.as_str()),
+            ("client_id", credentials.client_id.as_str()),
+        ];
+        if let Some(client_secret) = &credentials.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        self.http
+            .post(self.endpoint("token"))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RefreshTokenResponse>()
+            .await
+    }
+
+    /// Exchanges the code of the authorization code flow for tokens. The
+    /// redirect URI has to be the one the code was requested with.
+    pub async fn exchange_code(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        redirect_uri: &Url,
+    ) -> Result<RefreshTokenResponse, Error> {
+        self.http
+            .post(self.endpoint("token"))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri.as_str()),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RefreshTokenResponse>()
+            .await
+    }
+
+    /// Gets an app access token, which isn't tied to a user. App tokens
+    /// can't be refreshed, a new one is requested once it expired.
+    pub async fn request_app_access_token(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<AppTokenResponse, Error> {
+        self.http
+            .post(self.endpoint("token"))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<AppTokenResponse>()
+            .await
+    }
+
+    /// Revokes an access token so it can't be used anymore
+    pub async fn revoke_token(&self, client_id: &str, access_token: &str) -> Result<(), Error> {
+        self.http
+            .post(self.endpoint("revoke"))
+            .form(&[("client_id", client_id), ("token", access_token)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Checks that the access token is still valid. Twitch expects apps to
+    /// do this hourly. An invalid token fails with a 401 status.
+    pub async fn validate_token(&self, access_token: &str) -> Result<TokenValidation, Error> {
+        self.http
+            .get(self.endpoint("validate"))
+            .header("Authorization", format!("OAuth {access_token}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenValidation>()
+            .await
+    }
+}
+
+/// Gets the first refresh token of a user with the authorization code
+/// flow. The user opens `authorize_url` in a browser and Twitch redirects
+/// back to the redirect URI, which has to point to this machine, e.g.
+/// `http://localhost:3000/callback`, and be registered for the app.
+pub struct AuthorizationCodeFlow {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: Url,
+    scopes: Vec<String>,
+    // Random value checked on the redirect to reject forged requests
+    state: String,
+    timeout: Duration,
+    auth_client: AuthClient,
+}
+
+impl AuthorizationCodeFlow {
+    pub fn new(client_id: &str, client_secret: &str, redirect_uri: Url, scopes: &[&str]) -> Self {
+        AuthorizationCodeFlow {
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            redirect_uri,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            state: Alphanumeric.sample_string(&mut rand::thread_rng(), STATE_LENGTH),
+            timeout: DEFAULT_AUTHORIZATION_TIMEOUT,
+            auth_client: AuthClient::default(),
+        }
+    }
+
+    pub fn with_auth_client(mut self, auth_client: AuthClient) -> Self {
+        self.auth_client = auth_client;
+        self
+    }
+
+    /// How long `run` waits for the redirect
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The page where the user allows the app access to their account
+    pub fn authorize_url(&self) -> Url {
+        let mut url = self.auth_client.endpoint("authorize");
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", self.redirect_uri.as_str())
+            .append_pair("scope", &self.scopes.join(" "))
+            .append_pair("state", &self.state);
+        url
+    }
+
+    /// Listens on the redirect URI until Twitch redirects the user back,
+    /// then exchanges the code for the user's credentials. Fails with
+    /// `AuthorizationTimeout` if the redirect doesn't come in time.
+    pub async fn run(&self) -> Result<Credentials, AuthFlowError> {
+        let host = self
+            .redirect_uri
+            .host_str()
+            .ok_or_else(|| AuthFlowError::InvalidRedirectUri(self.redirect_uri.to_string()))?;
+        let port = self
+            .redirect_uri
+            .port_or_known_default()
+            .ok_or_else(|| AuthFlowError::InvalidRedirectUri(self.redirect_uri.to_string()))?;
+
+        let listener = TcpListener::bind((host, port))
+            .await
+            .map_err(AuthFlowError::Io)?;
+        let code = tokio::time::timeout(self.timeout, self.receive_code(&listener))
+            .await
+            .map_err(|_| AuthFlowError::AuthorizationTimeout)??;
+        self.exchange_code(&code).await
+    }
+
+    // Answers requests to the listener until one of them is the redirect.
+    // Connections are handled concurrently, so a client that never sends
+    // its request doesn't hold up the browser.
+    async fn receive_code(&self, listener: &TcpListener) -> Result<String, AuthFlowError> {
+        let mut connections = FuturesUnordered::new();
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted.map_err(AuthFlowError::Io)?;
+                    connections.push(self.handle_connection(stream));
+                }
+                Some(result) = connections.next() => {
+                    if let Some(result) = result {
+                        return result;
+                    }
+                }
+            }
+        }
+    }
+
+    // Answers one request, returning the result if it was the redirect
+    async fn handle_connection(
+        &self,
+        mut stream: TcpStream,
+    ) -> Option<Result<String, AuthFlowError>> {
+        let target = tokio::time::timeout(REQUEST_READ_TIMEOUT, read_request_target(&mut stream))
+            .await
+            .ok()??;
+
+        // Browsers also ask for things like the favicon
+        let url = self.redirect_uri.join(&target).ok();
+        let Some(url) = url.filter(|url| url.path() == self.redirect_uri.path()) else {
+            respond(&mut stream, "404 Not Found", "Not found").await;
+            return None;
+        };
+
+        // A forged request doesn't end the flow, the user may still come
+        let result = self.code_from_redirect(&url);
+        if let Err(AuthFlowError::StateMismatch) = result {
+            respond(&mut stream, "400 Bad Request", "Invalid state").await;
+            return None;
+        }
+
+        let page = match &result {
+            Ok(_) => "Authorization complete, you can close this window.",
+            Err(_) => "Authorization failed, see the application for details.",
+        };
+        respond(&mut stream, "200 OK", page).await;
+        Some(result)
+    }
+
+    fn code_from_redirect(&self, url: &Url) -> Result<String, AuthFlowError> {
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        if param("state").as_deref() != Some(self.state.as_str()) {
+            return Err(AuthFlowError::StateMismatch);
+        }
+        if let Some(error) = param("error") {
+            let description = param("error_description").unwrap_or_default();
+            return Err(AuthFlowError::AuthorizationDenied(error, description));
+        }
+        param("code").ok_or(AuthFlowError::MissingCode)
+    }
+
+    pub async fn exchange_code(&self, code: &str) -> Result<Credentials, AuthFlowError> {
+        let response = self
+            .auth_client
+            .exchange_code(
+                &self.client_id,
+                &self.client_secret,
+                code,
+                &self.redirect_uri,
+            )
+            .await
+            .map_err(AuthFlowError::Request)?;
+
+        Ok(Credentials {
+            refresh_token: response.refresh_token,
+            client_id: self.client_id.clone(),
+            client_secret: Some(self.client_secret.clone()),
+        })
+    }
+}
+
+// Reads an HTTP request and returns the target of its request line
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 || request.len() > MAX_REQUEST_LENGTH {
+            return None;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next()?.split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_owned()),
+        _ => None,
+    }
+}
+
+// Errors are ignored, the browser showing the page is only a courtesy
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/// Gets the first refresh token of a user with the device code flow, for
+/// machines without a browser. `request_device_code` returns the code the
+/// user enters at the verification URI on any device, while `poll` waits
+/// for them to do so.
+pub struct DeviceCodeFlow {
+    client_id: String,
+    // Only confidential apps have a client secret
+    client_secret: Option<String>,
+    scopes: Vec<String>,
+    auth_client: AuthClient,
+}
+
+impl DeviceCodeFlow {
+    pub fn new(client_id: &str, client_secret: Option<&str>, scopes: &[&str]) -> Self {
+        DeviceCodeFlow {
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.map(str::to_owned),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            auth_client: AuthClient::default(),
+        }
+    }
+
+    pub fn with_auth_client(mut self, auth_client: AuthClient) -> Self {
+        self.auth_client = auth_client;
+        self
+    }
+
+    pub async fn request_device_code(&self) -> Result<DeviceCode, AuthFlowError> {
+        self.auth_client
+            .http
+            .post(self.auth_client.endpoint("device"))
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("scopes", &self.scopes.join(" ")),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(AuthFlowError::Request)?
+            .json::<DeviceCode>()
+            .await
+            .map_err(AuthFlowError::Request)
+    }
+
+    /// Polls the token endpoint until the user authorized the device,
+    /// waiting the interval between polls and longer when asked to slow
+    /// down
+    pub async fn poll(&self, device_code: &DeviceCode) -> Result<Credentials, AuthFlowError> {
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(device_code.expires_in.into());
+        let mut interval = Duration::from_secs(device_code.interval.into());
+
+        loop {
+            tokio::time::sleep(interval).await;
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AuthFlowError::DeviceCodeExpired);
+            }
+
+            let error = match self.request_token(device_code).await? {
+                Ok(response) => {
+                    return Ok(Credentials {
+                        refresh_token: response.refresh_token,
+                        client_id: self.client_id.clone(),
+                        client_secret: self.client_secret.clone(),
+                    })
+                }
+                Err(error) => error,
+            };
+
+            match error.as_str() {
+                "authorization_pending" => {}
+                "slow_down" => interval += SLOW_DOWN_INCREMENT,
+                "expired_token" => return Err(AuthFlowError::DeviceCodeExpired),
+                "access_denied" => {
+                    return Err(AuthFlowError::AuthorizationDenied(error, String::new()))
+                }
+                _ => return Err(AuthFlowError::TokenRequestFailed(error)),
+            }
+        }
+    }
+
+    // Returns the tokens, or the error message of a 400 response
+    async fn request_token(
+        &self,
+        device_code: &DeviceCode,
+    ) -> Result<Result<RefreshTokenResponse, String>, AuthFlowError> {
+        let scopes = self.scopes.join(" ");
+        let mut form = vec![
+            ("client_id", self.client_id.as_str()),
+            ("scopes", scopes.as_str()),
+            ("device_code", device_code.device_code.as_str()),
+            ("grant_type", DEVICE_CODE_GRANT_TYPE),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .auth_client
+            .http
+            .post(self.auth_client.endpoint("token"))
+            .form(&form)
+            .send()
+            .await
+            .map_err(AuthFlowError::Request)?;
+
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error = response
+                .json::<TokenErrorResponse>()
+                .await
+                .map_err(AuthFlowError::Request)?;
+            return Ok(Err(error.message));
+        }
+
+        response
+            .error_for_status()
+            .map_err(AuthFlowError::Request)?
+            .json::<RefreshTokenResponse>()
+            .await
+            .map(Ok)
+            .map_err(AuthFlowError::Request)
+    }
+}
+
+/// Caches an app access token from the client credentials grant, only
+/// requesting a new one when there is none or it is about to expire
+pub struct AppAccessToken {
+    client_id: String,
+    client_secret: String,
+    auth_client: AuthClient,
+    // The token and when it expires
+    cached: Option<(String, tokio::time::Instant)>,
+}
+
+impl AppAccessToken {
+    pub fn new(client_id: &str, client_secret: &str) -> Self {
+        AppAccessToken {
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            auth_client: AuthClient::default(),
+            cached: None,
+        }
+    }
+
+    pub fn with_auth_client(mut self, auth_client: AuthClient) -> Self {
+        self.auth_client = auth_client;
+        self
+    }
+
+    /// Returns the cached token, requesting a new one if needed
+    pub async fn access_token(&mut self) -> Result<&str, Error> {
+        let now = tokio::time::Instant::now();
+        let is_fresh = |(_, expires_at): &(String, tokio::time::Instant)| {
+            expires_at.saturating_duration_since(now) > TOKEN_REFRESH_MARGIN
+        };
+
+        if !self.cached.as_ref().is_some_and(is_fresh) {
+            let response = self
+                .auth_client
+                .request_app_access_token(&self.client_id, &self.client_secret)
+                .await?;
+            let expires_at = now + Duration::from_secs(response.expires_in.into());
+            self.cached = Some((response.access_token, expires_at));
+        }
+
+        Ok(&self.cached.as_ref().expect("token was just requested").0)
+    }
+
+    /// When the cached token expires, None if there is none
+    pub fn expires_at(&self) -> Option<tokio::time::Instant> {
+        self.cached.as_ref().map(|(_, expires_at)| *expires_at)
+    }
+
+    /// Drops the cached token, e.g. after Twitch rejected it, so the next
+    /// call to `access_token` requests a new one
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+}
+
+lazy_static! {
+    // Used by the functions below, so they share one HTTP client
+    static ref TWITCH_AUTH_CLIENT: AuthClient = AuthClient::default();
+}
+
+/// `AuthClient::refresh_access_token` against Twitch's endpoint
+pub async fn refresh_access_token(
+    credentials: &Credentials,
+) -> Result<RefreshTokenResponse, Error> {
+    TWITCH_AUTH_CLIENT.refresh_access_token(credentials).await
+}
+
+/// `AuthClient::request_app_access_token` against Twitch's endpoint
+pub async fn request_app_access_token(
+    client_id: &str,
+    client_secret: &str,
+) -> Result<AppTokenResponse, Error> {
+    TWITCH_AUTH_CLIENT
+        .request_app_access_token(client_id, client_secret)
+        .await
+}
+
+/// `AuthClient::revoke_token` against Twitch's endpoint
+pub async fn revoke_token(client_id: &str, access_token: &str) -> Result<(), Error> {
+    TWITCH_AUTH_CLIENT
+        .revoke_token(client_id, access_token)
+        .await
+}
+
+/// `AuthClient::validate_token` against Twitch's endpoint
+pub async fn validate_token(access_token: &str) -> Result<TokenValidation, Error> {
+    TWITCH_AUTH_CLIENT.validate_token(access_token).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::task::JoinHandle;
+
+    // Answers one HTTP request with the status and JSON body, returning
+    // the request that was received
+    pub(crate) async fn http_stand_in(status: &str, body: &str) -> (Url, JoinHandle<String>) {
+        let (url, server) = http_stand_in_sequence(&[(status, body)]).await;
+        let request = tokio::spawn(async move { server.await.unwrap().remove(0) });
+        (url, request)
+    }
+
+    // Answers one HTTP request with each of the (status, JSON body)
+    // responses in order, returning the requests that were received
+    pub(crate) async fn http_stand_in_sequence(
+        responses: &[(&str, &str)],
+    ) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let responses = responses
+            .iter()
+            .map(|(status, body)| {
+                format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+
+                // Read the headers, then as much of the body as announced
+                while !is_complete_request(&request) {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    fn is_complete_request(request: &[u8]) -> bool {
+        let request = String::from_utf8_lossy(request);
+        let Some((headers, body)) = request.split_once("\r\n\r\n") else {
+            return false;
+        };
+
+        let content_length = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        body.len() >= content_length
+    }
+
+    #[tokio::test]
+    async fn test_validate_token() {
+        let (url, server) = http_stand_in(
+            "200 OK",
+            r#"{"client_id":"wbmytr93xzw8zbg0p1izqyzzc5mbiz","login":"twitchdev",
+            "scopes":["channel:read:subscriptions"],"user_id":"141981764","expires_in":5520838}"#,
+        )
+        .await;
+
+        let validation = AuthClient::new(url).validate_token("abc").await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.starts_with("GET /validate "));
+        assert!(request.contains("authorization: OAuth abc"));
+        assert_eq!(validation.login.as_deref(), Some("twitchdev"));
+        assert_eq!(validation.user_id.as_deref(), Some("141981764"));
+        assert_eq!(validation.scopes, vec!["channel:read:subscriptions"]);
+        assert_eq!(validation.expires_in, 5520838);
+    }
+
+    #[tokio::test]
+    async fn test_validate_invalid_token() {
+        let (url, _) = http_stand_in(
+            "401 Unauthorized",
+            r#"{"status":401,"message":"invalid access token"}"#,
+        )
+        .await;
+
+        let error = AuthClient::new(url)
+            .validate_token("abc")
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(reqwest::StatusCode::UNAUTHORIZED));
+    }
+
+    #[test]
+    fn test_authorize_url() {
+        let flow = AuthorizationCodeFlow::new(
+            "id",
+            "secret",
+            Url::parse("http://localhost:3000/callback").unwrap(),
+            &["chat:read", "chat:edit"],
+        );
+        let url = flow.authorize_url();
+        let params = url.query_pairs().into_owned().collect::<Vec<_>>();
+
+        assert!(url
+            .as_str()
+            .starts_with("https://id.twitch.tv/oauth2/authorize?"));
+        assert!(params.contains(&("client_id".to_owned(), "id".to_owned())));
+        assert!(params.contains(&(
+            "redirect_uri".to_owned(),
+            "http://localhost:3000/callback".to_owned()
+        )));
+        assert!(params.contains(&("scope".to_owned(), "chat:read chat:edit".to_owned())));
+        assert!(params.contains(&("state".to_owned(), flow.state.clone())));
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let (auth_url, token_server) = http_stand_in(
+            "200 OK",
+            r#"{"access_token":"access","expires_in":14124,"refresh_token":"refresh",
+            "scope":["chat:read"],"token_type":"bearer"}"#,
+        )
+        .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let redirect_uri = Url::parse(&format!(
+            "http://{}/callback",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let flow = AuthorizationCodeFlow::new("id", "secret", redirect_uri.clone(), &["chat:read"])
+            .with_auth_client(AuthClient::new(auth_url));
+
+        let browser = async {
+            // Connects without sending anything, which must not block the rest
+            let _stalled = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let favicon = redirect_uri.join("/favicon.ico").unwrap();
+            let forged = format!("{redirect_uri}?code=xyz&state=wrong");
+            let redirect = format!(
+                "{redirect_uri}?code=abc&scope=chat%3Aread&state={}",
+                flow.state
+            );
+
+            let favicon = reqwest::get(favicon).await.unwrap().status();
+            let forged = reqwest::get(forged).await.unwrap().status();
+            let redirect = reqwest::get(redirect).await.unwrap().status();
+            (favicon, forged, redirect)
+        };
+        let (code, (favicon, forged, redirect)) =
+            tokio::join!(flow.receive_code(&listener), browser);
+
+        assert_eq!(favicon, reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(forged, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(redirect, reqwest::StatusCode::OK);
+        assert_eq!(code.unwrap(), "abc");
+
+        let credentials = flow.exchange_code("abc").await.unwrap();
+        let request = token_server.await.unwrap();
+        assert_eq!(credentials.refresh_token, "refresh");
+        assert!(request.contains("grant_type=authorization_code"));
+        assert!(request.contains("code=abc"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_authorization_code_flow_times_out() {
+        let redirect_uri = Url::parse("http://127.0.0.1:0/callback").unwrap();
+        let flow = AuthorizationCodeFlow::new("id", "secret", redirect_uri, &["chat:read"])
+            .timeout(Duration::from_secs(60));
+
+        assert!(matches!(
+            flow.run().await,
+            Err(AuthFlowError::AuthorizationTimeout)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_device_code_flow() {
+        let (url, server) = http_stand_in_sequence(&[
+            (
+                "200 OK",
+                r#"{"device_code":"device","expires_in":1800,"interval":5,
+                "user_code":"ABCDEFGH","verification_uri":"https://www.twitch.tv/activate"}"#,
+            ),
+            (
+                "400 Bad Request",
+                r#"{"status":400,"message":"authorization_pending"}"#,
+            ),
+            ("400 Bad Request", r#"{"status":400,"message":"slow_down"}"#),
+            (
+                "200 OK",
+                r#"{"access_token":"access","expires_in":14124,"refresh_token":"refresh",
+                "scope":["chat:read"],"token_type":"bearer"}"#,
+            ),
         ])
-        .send()
-        .await?
-        .json::<RefreshTokenResponse>()
-        .await?;
+        .await;
+        let flow =
+            DeviceCodeFlow::new("id", None, &["chat:read"]).with_auth_client(AuthClient::new(url));
+
+        let device_code = flow.request_device_code().await.unwrap();
+        assert_eq!(device_code.user_code, "ABCDEFGH");
+
+        let started = tokio::time::Instant::now();
+        let credentials = flow.poll(&device_code).await.unwrap();
+        let requests = server.await.unwrap();
+
+        // Waited 5s twice, then 10s after slowing down
+        assert_eq!(started.elapsed(), Duration::from_secs(20));
+        assert_eq!(credentials.refresh_token, "refresh");
+        assert!(requests[0].starts_with("POST /device "));
+        assert!(requests[3].contains("device_code=device"));
+        assert!(!requests[3].contains("client_secret"));
+        assert!(credentials.client_secret.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_public_client() {
+        let (url, server) = http_stand_in(
+            "200 OK",
+            r#"{"access_token":"access","expires_in":14124,"refresh_token":"refresh",
+            "scope":["chat:read"],"token_type":"bearer"}"#,
+        )
+        .await;
+        let credentials = Credentials {
+            refresh_token: "refresh".to_owned(),
+            client_id: "id".to_owned(),
+            client_secret: None,
+        };
+
+        AuthClient::new(url)
+            .refresh_access_token(&credentials)
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.contains("grant_type=refresh_token"));
+        assert!(request.contains("client_id=id"));
+        assert!(!request.contains("client_secret"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_app_access_token_is_cached() {
+        let (url, server) = http_stand_in_sequence(&[
+            (
+                "200 OK",
+                r#"{"access_token":"first","expires_in":3600,"token_type":"bearer"}"#,
+            ),
+            (
+                "200 OK",
+                r#"{"access_token":"second","expires_in":3600,"token_type":"bearer"}"#,
+            ),
+        ])
+        .await;
+        let mut token = AppAccessToken::new("id", "secret").with_auth_client(AuthClient::new(url));
+
+        assert_eq!(token.access_token().await.unwrap(), "first");
+        tokio::time::advance(Duration::from_secs(3000)).await;
+        assert_eq!(token.access_token().await.unwrap(), "first");
+
+        // Renewed within the margin before it expires
+        tokio::time::advance(Duration::from_secs(301)).await;
+        assert_eq!(token.access_token().await.unwrap(), "second");
 
-    Ok(res.access_token)
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("grant_type=client_credentials"));
+    }
 }
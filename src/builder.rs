@@ -0,0 +1,348 @@
+use std::time::Duration;
+use url::Url;
+
+use crate::credentials::Credentials;
+use crate::error::{ConfigError, Error};
+use crate::rate_limit::{Limit, RateLimits};
+use crate::reconnect::ReconnectPolicy;
+use crate::token_store::TokenStore;
+use crate::transport;
+use crate::twitch_client::{
+    self, Capability, TokenRefreshCallback, TwitchClient, DEFAULT_AUTH_TIMEOUT,
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_JOIN_TIMEOUT,
+};
+
+// Twitch login names are at most 25 characters
+const MAX_NAME_LENGTH: usize = 25;
+
+/// Collects the options of a `TwitchClient`. `build` validates them and
+/// returns a client that is connected, authenticated and in the channels.
+pub struct TwitchClientBuilder {
+    // None for an anonymous read-only client
+    credentials: Option<Credentials>,
+    nick: String,
+    auto_pong: bool,
+    url: Url,
+    capabilities: Vec<Capability>,
+    channels: Vec<String>,
+    reconnect_policy: ReconnectPolicy,
+    rate_limits: RateLimits,
+    connect_timeout: Duration,
+    join_timeout: Duration,
+    auth_timeout: Duration,
+    refresh_on_auth_failure: bool,
+    revoke_on_shutdown: bool,
+    token_refresh_callback: Option<TokenRefreshCallback>,
+    token_store: Option<Box<dyn TokenStore>>,
+    auth_url: Option<Url>,
+    token_validation_interval: Option<Duration>,
+}
+
+impl TwitchClientBuilder {
+    pub fn new(credentials: Credentials, nick: &str) -> Self {
+        Self::with_credentials(Some(credentials), nick.to_owned())
+    }
+
+    /// Configures a read-only client, see `TwitchClient::anonymous`
+    pub fn anonymous() -> Self {
+        Self::with_credentials(None, twitch_client::anonymous_nick())
+    }
+
+    fn with_credentials(credentials: Option<Credentials>, nick: String) -> Self {
+        TwitchClientBuilder {
+            credentials,
+            nick,
+            auto_pong: true,
+            url: Url::parse(transport::TWITCH_WEBSOCKET_URL).unwrap(),
+            capabilities: Vec::new(),
+            channels: Vec::new(),
+            reconnect_policy: ReconnectPolicy::default(),
+            rate_limits: RateLimits::default(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            auth_timeout: DEFAULT_AUTH_TIMEOUT,
+            refresh_on_auth_failure: false,
+            revoke_on_shutdown: false,
+            token_refresh_callback: None,
+            token_store: None,
+            auth_url: None,
+            token_validation_interval: None,
+        }
+    }
+
+    /// Whether the client answers PINGs itself, enabled by default
+    pub fn auto_pong(mut self, auto_pong: bool) -> Self {
+        self.auto_pong = auto_pong;
+        self
+    }
+
+    pub fn url(mut self, url: Url) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: &[Capability]) -> Self {
+        self.capabilities.extend_from_slice(capabilities);
+        self
+    }
+
+    /// Adds a channel to join, with or without the leading `#`
+    pub fn channel(mut self, channel_name: &str) -> Self {
+        let channel_name = channel_name.strip_prefix('#').unwrap_or(channel_name);
+        self.channels.push(channel_name.to_owned());
+        self
+    }
+
+    pub fn channels<'a>(self, channel_names: impl IntoIterator<Item = &'a str>) -> Self {
+        channel_names
+            .into_iter()
+            .fold(self, |builder, channel_name| builder.channel(channel_name))
+    }
+
+    pub fn reconnect_policy(mut self, reconnect_policy: ReconnectPolicy) -> Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
+
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn join_timeout(mut self, join_timeout: Duration) -> Self {
+        self.join_timeout = join_timeout;
+        self
+    }
+
+    pub fn auth_timeout(mut self, auth_timeout: Duration) -> Self {
+        self.auth_timeout = auth_timeout;
+        self
+    }
+
+    /// See `TwitchClient::set_refresh_on_auth_failure`
+    pub fn refresh_on_auth_failure(mut self, refresh_on_auth_failure: bool) -> Self {
+        self.refresh_on_auth_failure = refresh_on_auth_failure;
+        self
+    }
+
+    /// See `TwitchClient::set_revoke_on_shutdown`
+    pub fn revoke_on_shutdown(mut self, revoke_on_shutdown: bool) -> Self {
+        self.revoke_on_shutdown = revoke_on_shutdown;
+        self
+    }
+
+    /// See `TwitchClient::set_token_refresh_callback`. Set here, it is also
+    /// notified of the refresh done while building the client.
+    pub fn on_token_refresh(mut self, callback: impl FnMut(&Credentials) + Send + 'static) -> Self {
+        self.token_refresh_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// See `TwitchClient::set_token_store`
+    pub fn token_store(mut self, token_store: impl TokenStore + 'static) -> Self {
+        self.token_store = Some(Box::new(token_store));
+        self
+    }
+
+    /// See `TwitchClient::set_auth_url`
+    pub fn auth_url(mut self, auth_url: Url) -> Self {
+        self.auth_url = Some(auth_url);
+        self
+    }
+
+    /// See `TwitchClient::set_token_validation_interval`
+    pub fn token_validation_interval(mut self, interval: Duration) -> Self {
+        self.token_validation_interval = Some(interval);
+        self
+    }
+
+    /// Checks the options without connecting
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.nick.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(ConfigError::NickNotLowercase(self.nick.clone()));
+        }
+        if !is_valid_name(&self.nick) {
+            return Err(ConfigError::InvalidNick(self.nick.clone()));
+        }
+        if let Some(channel_name) = self.channels.iter().find(|c| !is_valid_name(c)) {
+            return Err(ConfigError::InvalidChannelName(channel_name.clone()));
+        }
+        if !transport::is_supported_scheme(self.url.scheme()) {
+            return Err(ConfigError::UnsupportedUrlScheme(
+                self.url.scheme().to_owned(),
+            ));
+        }
+
+        let policy = &self.reconnect_policy;
+        if policy.base_delay > policy.max_delay {
+            return Err(ConfigError::InvalidReconnectPolicy(
+                "base_delay is longer than max_delay".to_owned(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&policy.jitter) {
+            return Err(ConfigError::InvalidReconnectPolicy(format!(
+                "jitter {} is not between 0.0 and 1.0",
+                policy.jitter
+            )));
+        }
+
+        let limits = [
+            ("normal", self.rate_limits.normal),
+            ("moderator", self.rate_limits.moderator),
+            ("joins", self.rate_limits.joins),
+        ];
+        if let Some((name, _)) = limits.iter().find(|(_, limit)| !is_valid_limit(limit)) {
+            return Err(ConfigError::InvalidRateLimit(name.to_string()));
+        }
+
+        if self.connect_timeout.is_zero() {
+            return Err(ConfigError::InvalidTimeout("connect_timeout".to_owned()));
+        }
+        if self.join_timeout.is_zero() {
+            return Err(ConfigError::InvalidTimeout("join_timeout".to_owned()));
+        }
+        if self.auth_timeout.is_zero() {
+            return Err(ConfigError::InvalidTimeout("auth_timeout".to_owned()));
+        }
+        if self.token_validation_interval == Some(Duration::ZERO) {
+            return Err(ConfigError::InvalidTimeout(
+                "token_validation_interval".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates the options, then fetches an access token unless the
+    /// client is anonymous, connects, authenticates, requests the
+    /// capabilities and joins the channels
+    pub async fn build(self) -> Result<TwitchClient, Error> {
+        self.validate()?;
+
+        let mut client =
+            TwitchClient::with_credentials(self.credentials, self.nick, self.auto_pong);
+        client.set_url(self.url);
+        client.set_reconnect_policy(self.reconnect_policy);
+        client.set_rate_limits(self.rate_limits);
+        client.set_connect_timeout(self.connect_timeout);
+        client.set_join_timeout(self.join_timeout);
+        client.set_auth_timeout(self.auth_timeout);
+        client.set_refresh_on_auth_failure(self.refresh_on_auth_failure);
+        client.set_revoke_on_shutdown(self.revoke_on_shutdown);
+        if let Some(callback) = self.token_refresh_callback {
+            client.set_token_refresh_callback(callback);
+        }
+        if let Some(token_store) = self.token_store {
+            client.set_token_store(token_store);
+        }
+        if let Some(auth_url) = self.auth_url {
+            client.set_auth_url(auth_url);
+        }
+        client.set_token_validation_interval(self.token_validation_interval);
+
+        client.update_access_token().await?;
+        client.connect().await?;
+        client.authenticate().await?;
+        if !self.capabilities.is_empty() {
+            client.cap_req(&self.capabilities).await?;
+        }
+        for channel_name in &self.channels {
+            client.join(channel_name).await?;
+        }
+        Ok(client)
+    }
+}
+
+// Login names only contain lowercase letters, digits and underscores
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_valid_limit(limit: &Limit) -> bool {
+    limit.messages > 0 && !limit.period.is_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(nick: &str) -> TwitchClientBuilder {
+        let credentials = Credentials {
+            refresh_token: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+        };
+
+        TwitchClientBuilder::new(credentials, nick)
+    }
+
+    #[test]
+    fn test_validate_names() {
+        assert!(builder("my_bot123")
+            .channels(["#xyz", "abc"])
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            builder("MyBot").validate(),
+            Err(ConfigError::NickNotLowercase(_))
+        ));
+        assert!(matches!(
+            builder("my bot").validate(),
+            Err(ConfigError::InvalidNick(_))
+        ));
+        assert!(matches!(
+            builder("bot").channel("#").validate(),
+            Err(ConfigError::InvalidChannelName(_))
+        ));
+        assert!(matches!(
+            builder("bot").channel("Xyz").validate(),
+            Err(ConfigError::InvalidChannelName(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert!(matches!(
+            builder("bot")
+                .url(Url::parse("https://irc-ws.chat.twitch.tv").unwrap())
+                .validate(),
+            Err(ConfigError::UnsupportedUrlScheme(_))
+        ));
+        assert!(matches!(
+            builder("bot")
+                .reconnect_policy(ReconnectPolicy {
+                    jitter: 2.0,
+                    ..ReconnectPolicy::default()
+                })
+                .validate(),
+            Err(ConfigError::InvalidReconnectPolicy(_))
+        ));
+
+        let mut rate_limits = RateLimits::default();
+        rate_limits.joins.messages = 0;
+        assert!(matches!(
+            builder("bot").rate_limits(rate_limits).validate(),
+            Err(ConfigError::InvalidRateLimit(_))
+        ));
+        assert!(matches!(
+            builder("bot").connect_timeout(Duration::ZERO).validate(),
+            Err(ConfigError::InvalidTimeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_build_fails_before_connecting() {
+        assert!(matches!(
+            builder("MyBot").build().await,
+            Err(Error::ConfigError(ConfigError::NickNotLowercase(_)))
+        ));
+    }
+}
@@ -0,0 +1,541 @@
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::event_handler::{EventHandler, HandlerError, HandlerResult};
+use crate::irc::UserContext;
+use crate::tags::Badge;
+use crate::twitch_client::TwitchClient;
+
+/// Roles of a chatter in a channel, from least to most privileged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Everyone,
+    Subscriber,
+    Vip,
+    Moderator,
+    Broadcaster,
+}
+
+impl Role {
+    pub fn of(user_context: &UserContext) -> Role {
+        if user_context.is_broadcaster {
+            Role::Broadcaster
+        } else if user_context.is_mod {
+            Role::Moderator
+        } else if user_context
+            .badges
+            .iter()
+            .any(|b| matches!(b, Badge::Vip(_)))
+        {
+            Role::Vip
+        } else if user_context.is_subscriber {
+            Role::Subscriber
+        } else {
+            Role::Everyone
+        }
+    }
+}
+
+/// A chat command invocation passed to the command's handler
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub channel: String,
+    // Name or alias the command was called with, without the prefix
+    pub name: String,
+    pub args: Vec<String>,
+    pub user_context: UserContext,
+}
+
+/// Text to reply with in the channel, if any
+pub type CommandResult = Result<Option<String>, HandlerError>;
+
+type CommandHandler = Box<dyn Fn(Invocation) -> BoxFuture<'static, CommandResult> + Send + Sync>;
+
+pub struct Command {
+    name: String,
+    aliases: Vec<String>,
+    role: Role,
+    // Chatters need one of these badges unless the list is empty
+    badges: Vec<Badge>,
+    user_cooldown: Duration,
+    global_cooldown: Duration,
+    handler: CommandHandler,
+}
+
+impl Command {
+    pub fn new<F, Fut>(name: &str, handler: F) -> Self
+    where
+        F: Fn(Invocation) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CommandResult> + Send + 'static,
+    {
+        Command {
+            name: name.to_lowercase(),
+            aliases: Vec::new(),
+            role: Role::Everyone,
+            badges: Vec::new(),
+            user_cooldown: Duration::ZERO,
+            global_cooldown: Duration::ZERO,
+            handler: Box::new(move |invocation| Box::pin(handler(invocation))),
+        }
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.to_lowercase());
+        self
+    }
+
+    /// Minimum role needed to use the command
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Allows chatters with the badge regardless of its version, e.g.
+    /// `Badge::Subscriber(0)` allows every subscriber badge. `Badge::Other`
+    /// only allows badges with the same name.
+    pub fn badge(mut self, badge: Badge) -> Self {
+        self.badges.push(badge);
+        self
+    }
+
+    /// Time a chatter has to wait before using the command again in the
+    /// same channel
+    pub fn user_cooldown(mut self, cooldown: Duration) -> Self {
+        self.user_cooldown = cooldown;
+        self
+    }
+
+    /// Time anyone has to wait before the command can be used again in the
+    /// same channel
+    pub fn global_cooldown(mut self, cooldown: Duration) -> Self {
+        self.global_cooldown = cooldown;
+        self
+    }
+
+    fn is_allowed(&self, user_context: &UserContext) -> bool {
+        let has_badge = self.badges.is_empty()
+            || self.badges.iter().any(|required| {
+                user_context
+                    .badges
+                    .iter()
+                    .any(|b| is_same_badge(b, required))
+            });
+
+        Role::of(user_context) >= self.role && has_badge
+    }
+}
+
+// Compares badges without their version
+fn is_same_badge(badge: &Badge, other: &Badge) -> bool {
+    match (badge, other) {
+        (Badge::Other(name, _), Badge::Other(other_name, _)) => name == other_name,
+        _ => mem::discriminant(badge) == mem::discriminant(other),
+    }
+}
+
+/// What happened to a chat message passed to the router
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandOutcome {
+    NotACommand,
+    UnknownCommand,
+    NotAllowed,
+    CoolingDown(Duration),
+    Executed,
+}
+
+// Result of matching a message against the registered commands
+enum Route {
+    Outcome(CommandOutcome),
+    Execute(usize, Invocation),
+}
+
+/// Routes chat messages starting with the prefix to registered commands.
+/// Implements `EventHandler`, so it can be passed to `TwitchClient::run`.
+pub struct CommandRouter {
+    prefix: String,
+    commands: Vec<Command>,
+    // Maps names and aliases to the index of the command
+    names: HashMap<String, usize>,
+    // Cooldowns are per channel. Entries are removed once the cooldown is
+    // over, so only commands still cooling down are kept.
+    last_used: HashMap<(String, usize), Instant>,
+    last_used_by_user: HashMap<(String, usize, String), Instant>,
+}
+
+impl CommandRouter {
+    pub fn new(prefix: &str) -> Self {
+        CommandRouter {
+            prefix: prefix.to_owned(),
+            commands: Vec::new(),
+            names: HashMap::new(),
+            last_used: HashMap::new(),
+            last_used_by_user: HashMap::new(),
+        }
+    }
+
+    /// Adds a command. Names and aliases that are already registered are
+    /// taken over by the new command.
+    pub fn register(&mut self, command: Command) {
+        let index = self.commands.len();
+        for name in std::iter::once(&command.name).chain(&command.aliases) {
+            self.names.insert(name.clone(), index);
+        }
+        self.commands.push(command);
+    }
+
+    fn route(
+        &mut self,
+        channel: &str,
+        user_context: &UserContext,
+        message: &str,
+        now: Instant,
+    ) -> Route {
+        let input = match message.strip_prefix(&self.prefix) {
+            Some(input) if !self.prefix.is_empty() => input,
+            _ => return Route::Outcome(CommandOutcome::NotACommand),
+        };
+
+        let mut args = parse_arguments(input);
+        if args.is_empty() {
+            return Route::Outcome(CommandOutcome::NotACommand);
+        }
+        let name = args.remove(0).to_lowercase();
+
+        let index = match self.names.get(&name) {
+            Some(index) => *index,
+            None => return Route::Outcome(CommandOutcome::UnknownCommand),
+        };
+        self.prune_cooldowns(now);
+        if !self.commands[index].is_allowed(user_context) {
+            return Route::Outcome(CommandOutcome::NotAllowed);
+        }
+
+        let key = (channel.to_owned(), index);
+        let user_key = (channel.to_owned(), index, user_context.user_id.clone());
+        let command = &self.commands[index];
+        let remaining = [
+            self.last_used
+                .get(&key)
+                .map(|t| remaining_cooldown(command.global_cooldown, *t, now)),
+            self.last_used_by_user
+                .get(&user_key)
+                .map(|t| remaining_cooldown(command.user_cooldown, *t, now)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+
+        if !remaining.is_zero() {
+            return Route::Outcome(CommandOutcome::CoolingDown(remaining));
+        }
+
+        if !command.global_cooldown.is_zero() {
+            self.last_used.insert(key, now);
+        }
+        if !command.user_cooldown.is_zero() {
+            self.last_used_by_user.insert(user_key, now);
+        }
+
+        Route::Execute(
+            index,
+            Invocation {
+                channel: channel.to_owned(),
+                name,
+                args,
+                user_context: user_context.clone(),
+            },
+        )
+    }
+
+    // Forgets uses whose cooldown is over
+    fn prune_cooldowns(&mut self, now: Instant) {
+        let commands = &self.commands;
+        self.last_used.retain(|(_, index), t| {
+            !remaining_cooldown(commands[*index].global_cooldown, *t, now).is_zero()
+        });
+        self.last_used_by_user.retain(|(_, index, _), t| {
+            !remaining_cooldown(commands[*index].user_cooldown, *t, now).is_zero()
+        });
+    }
+
+    /// Runs the command in the message if there is one and the chatter is
+    /// allowed to use it, replying in the channel with the text returned
+    /// by the command
+    pub async fn handle(
+        &mut self,
+        client: &mut TwitchClient,
+        channel: &str,
+        user_context: &UserContext,
+        message: &str,
+    ) -> Result<CommandOutcome, HandlerError> {
+        let (index, invocation) = match self.route(channel, user_context, message, Instant::now()) {
+            Route::Outcome(outcome) => return Ok(outcome),
+            Route::Execute(index, invocation) => (index, invocation),
+        };
+
+        if let Some(reply) = (self.commands[index].handler)(invocation).await? {
+            client.privmsg(channel, &reply).await?;
+        }
+        Ok(CommandOutcome::Executed)
+    }
+}
+
+#[async_trait]
+impl EventHandler for CommandRouter {
+    async fn on_privmsg(
+        &mut self,
+        client: &mut TwitchClient,
+        channel: &str,
+        user_context: &UserContext,
+        message: &str,
+    ) -> HandlerResult {
+        self.handle(client, channel, user_context, message).await?;
+        Ok(())
+    }
+}
+
+fn remaining_cooldown(cooldown: Duration, last_used: Instant, now: Instant) -> Duration {
+    cooldown.saturating_sub(now.saturating_duration_since(last_used))
+}
+
+/// Splits command input into arguments on whitespace. Double quotes group
+/// words into one argument, and a backslash escapes the next character.
+pub fn parse_arguments(input: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut in_quotes = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.extend(chars.next());
+                in_argument = true;
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                in_argument = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_argument {
+                    args.push(mem::take(&mut current));
+                    in_argument = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_argument = true;
+            }
+        }
+    }
+
+    if in_argument {
+        args.push(current);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irc::{parse_message, IRCMessage};
+
+    fn user_context(badges: &str, is_mod: bool) -> UserContext {
+        let line = format!(
+            "@user-type=;user-id=1;badges={badges};mod={};returning-chatter=0;first-msg=0;turbo=0;subscriber=0;display-name=abc \
+            :abc!abc@abc.tmi.twitch.tv PRIVMSG #xyz :!hi",
+            u8::from(is_mod)
+        );
+
+        match parse_message(&line).unwrap() {
+            IRCMessage::Privmsg { user_context, .. } => user_context,
+            other => panic!("expected PRIVMSG, got {other:?}"),
+        }
+    }
+
+    fn router() -> CommandRouter {
+        let mut router = CommandRouter::new("!");
+        router.register(
+            Command::new("hello", |_| async { Ok(Some("hi".to_owned())) })
+                .alias("hi")
+                .user_cooldown(Duration::from_secs(10)),
+        );
+        router.register(Command::new("ban", |_| async { Ok(None) }).role(Role::Moderator));
+        router.register(Command::new("vip", |_| async { Ok(None) }).badge(Badge::Vip(0)));
+        router.register(
+            Command::new("prime", |_| async { Ok(None) })
+                .badge(Badge::Other("premium".to_owned(), 0)),
+        );
+        router.register(
+            Command::new("roll", |_| async { Ok(None) }).global_cooldown(Duration::from_secs(30)),
+        );
+        router
+    }
+
+    fn outcome(route: Route) -> CommandOutcome {
+        match route {
+            Route::Outcome(outcome) => outcome,
+            Route::Execute(..) => CommandOutcome::Executed,
+        }
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        assert_eq!(
+            parse_arguments(r#"hello "big world"  x\"y "" end"#),
+            vec!["hello", "big world", "x\"y", "", "end"]
+        );
+        assert!(parse_arguments("   ").is_empty());
+    }
+
+    #[test]
+    fn test_route_by_name_and_alias() {
+        let mut router = router();
+        let user = user_context("", false);
+        let now = Instant::now();
+
+        match router.route("xyz", &user, "!HI there \"you all\"", now) {
+            Route::Execute(_, invocation) => {
+                assert_eq!(invocation.name, "hi");
+                assert_eq!(invocation.args, vec!["there", "you all"]);
+            }
+            Route::Outcome(outcome) => panic!("expected execution, got {outcome:?}"),
+        }
+        assert_eq!(
+            outcome(router.route("xyz", &user, "hello", now)),
+            CommandOutcome::NotACommand
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!nope", now)),
+            CommandOutcome::UnknownCommand
+        );
+    }
+
+    #[test]
+    fn test_permissions() {
+        let mut router = router();
+        let now = Instant::now();
+
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("", false), "!ban", now)),
+            CommandOutcome::NotAllowed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("", true), "!ban", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("broadcaster/1", false), "!ban", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("", false), "!vip", now)),
+            CommandOutcome::NotAllowed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("vip/1", false), "!vip", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("turbo/1", false), "!prime", now)),
+            CommandOutcome::NotAllowed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("bits-charity/1", false), "!prime", now)),
+            CommandOutcome::NotAllowed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user_context("premium/1", false), "!prime", now)),
+            CommandOutcome::Executed
+        );
+    }
+
+    #[test]
+    fn test_user_cooldown() {
+        let mut router = router();
+        let user = user_context("", false);
+        let now = Instant::now();
+
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!hello", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!hi", now + Duration::from_secs(4))),
+            CommandOutcome::CoolingDown(Duration::from_secs(6))
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!hello", now + Duration::from_secs(10))),
+            CommandOutcome::Executed
+        );
+    }
+
+    #[test]
+    fn test_global_cooldown() {
+        let mut router = router();
+        let first = user_context("", false);
+        let mut second = first.clone();
+        second.user_id = "2".to_owned();
+        let now = Instant::now();
+
+        assert_eq!(
+            outcome(router.route("xyz", &first, "!roll", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &second, "!roll", now + Duration::from_secs(10))),
+            CommandOutcome::CoolingDown(Duration::from_secs(20))
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &second, "!hello", now + Duration::from_secs(10))),
+            CommandOutcome::Executed
+        );
+    }
+
+    #[test]
+    fn test_cooldowns_are_per_channel() {
+        let mut router = router();
+        let user = user_context("", false);
+        let now = Instant::now();
+
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!roll", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("abc", &user, "!roll", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("abc", &user, "!hello", now)),
+            CommandOutcome::Executed
+        );
+        assert_eq!(
+            outcome(router.route("xyz", &user, "!hello", now)),
+            CommandOutcome::Executed
+        );
+    }
+
+    #[test]
+    fn test_expired_cooldowns_are_pruned() {
+        let mut router = router();
+        let user = user_context("", false);
+        let now = Instant::now();
+
+        router.route("xyz", &user, "!hello", now);
+        router.route("xyz", &user, "!roll", now);
+        router.route("xyz", &user, "!ban", now);
+        assert_eq!(router.last_used.len(), 1);
+        assert_eq!(router.last_used_by_user.len(), 1);
+
+        router.route("abc", &user, "!vip", now + Duration::from_secs(30));
+        assert!(router.last_used.is_empty());
+        assert!(router.last_used_by_user.is_empty());
+    }
+}
@@ -1,5 +1,7 @@
+#[derive(Clone)]
 pub struct Credentials {
     pub refresh_token: String,
     pub client_id: String,
-    pub client_secret: String,
+    // None for public clients, which refresh tokens without a secret
+    pub client_secret: Option<String>,
 }
@@ -1,4 +1,8 @@
 use thiserror::Error;
+use tokio_native_tls::native_tls;
+use tokio_tungstenite::tungstenite;
+
+use crate::scope::Scope;
 
 #[derive(Debug, Error)]
 pub enum MessageParseError {
@@ -26,21 +30,150 @@ pub enum MessageParseError {
 
     #[error("user type {0} is invalid")]
     InvalidUserType(String),
+
+    #[error("sub plan {0} is invalid")]
+    InvalidSubPlan(String),
+
+    #[error("value for tag tmi-sent-ts is not a valid timestamp: {0}")]
+    InvalidTimestamp(String),
+
+    #[error("no channel in command: {0}")]
+    MissingChannel(String),
+}
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("url is not supported: {0}")]
+    UnsupportedUrl(String),
+
+    #[error("io error: {0}")]
+    Io(std::io::Error),
+
+    #[error("tls error: {0}")]
+    Tls(native_tls::Error),
+
+    #[error("websocket error: {0}")]
+    WebSocket(tungstenite::Error),
+
+    #[error("received text is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+impl TransportError {
+    /// Whether the connection can't be used anymore after the error. Text
+    /// that isn't valid UTF-8 only loses that text.
+    pub fn is_connection_lost(&self) -> bool {
+        !matches!(self, Self::InvalidUtf8)
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<native_tls::Error> for TransportError {
+    fn from(value: native_tls::Error) -> Self {
+        Self::Tls(value)
+    }
+}
+
+impl From<tungstenite::Error> for TransportError {
+    fn from(value: tungstenite::Error) -> Self {
+        match value {
+            tungstenite::Error::Utf8 => Self::InvalidUtf8,
+            tungstenite::Error::Io(e) => Self::Io(e),
+            e => Self::WebSocket(e),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum ConnectionError {
-    #[error("websocket is not connected")]
-    WebsocketNotConnected,
+    #[error("not connected to the server")]
+    NotConnected,
 
-    #[error("error opening websocket connection: {0}")]
-    WebsocketConnectionError(tokio_tungstenite::tungstenite::Error),
+    #[error("error opening connection: {0}")]
+    ConnectFailure(TransportError),
 
     #[error("error sending message: {0}")]
-    SendMessageFailure(tokio_tungstenite::tungstenite::Error),
+    SendMessageFailure(TransportError),
 
     #[error("error receiving message: {0}")]
-    ReceiveMessageFailure(tokio_tungstenite::tungstenite::Error),
+    ReceiveMessageFailure(TransportError),
+
+    #[error("timed out connecting to the server")]
+    ConnectTimeout,
+
+    #[error("connection was closed by the server")]
+    ConnectionClosed,
+
+    #[error("the client owning the connection was dropped")]
+    ClientDropped,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("nick must be lowercase: {0}")]
+    NickNotLowercase(String),
+
+    #[error("nick is invalid: {0}")]
+    InvalidNick(String),
+
+    #[error("channel name is invalid: {0}")]
+    InvalidChannelName(String),
+
+    #[error("url scheme is not supported: {0}")]
+    UnsupportedUrlScheme(String),
+
+    #[error("reconnect policy is invalid: {0}")]
+    InvalidReconnectPolicy(String),
+
+    #[error("rate limit is invalid: {0}")]
+    InvalidRateLimit(String),
+
+    #[error("timeout must be greater than zero: {0}")]
+    InvalidTimeout(String),
+}
+
+#[derive(Debug, Error)]
+pub enum TokenStoreError {
+    #[error("error accessing token file: {0}")]
+    Io(std::io::Error),
+
+    #[error("token file is not valid JSON: {0}")]
+    Json(serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum AuthFlowError {
+    #[error("redirect uri needs a host and port: {0}")]
+    InvalidRedirectUri(String),
+
+    #[error("error listening for the redirect: {0}")]
+    Io(std::io::Error),
+
+    #[error("state in the redirect doesn't match the request")]
+    StateMismatch,
+
+    #[error("authorization was denied: {0} {1}")]
+    AuthorizationDenied(String, String),
+
+    #[error("redirect has no authorization code")]
+    MissingCode,
+
+    #[error("user didn't authorize the app in time")]
+    AuthorizationTimeout,
+
+    #[error("device code expired before the user authorized the device")]
+    DeviceCodeExpired,
+
+    #[error("token request failed: {0}")]
+    TokenRequestFailed(String),
+
+    #[error("error requesting tokens: {0}")]
+    Request(reqwest::Error),
 }
 
 #[derive(Debug, Error)]
@@ -53,6 +186,30 @@ pub enum Error {
 
     #[error("error refreshing access token: {0}")]
     RefreshAccessTokenError(reqwest::Error),
+
+    #[error("error validating access token: {0}")]
+    ValidateTokenError(reqwest::Error),
+
+    #[error("error revoking access token: {0}")]
+    RevokeTokenError(reqwest::Error),
+
+    #[error("server rejected the login: {0}")]
+    AuthenticationFailed(String),
+
+    #[error("timed out waiting for the server to accept the login")]
+    AuthenticationTimeout,
+
+    #[error("access token is missing the {0} scope")]
+    MissingScope(Scope),
+
+    #[error("anonymous clients can't send chat messages")]
+    AnonymousReadOnly,
+
+    #[error("error loading or saving credentials: {0}")]
+    TokenStoreError(TokenStoreError),
+
+    #[error("invalid client configuration: {0}")]
+    ConfigError(ConfigError),
 }
 
 impl From<ConnectionError> for Error {
@@ -60,3 +217,15 @@ impl From<ConnectionError> for Error {
         Self::ConnectionError(value)
     }
 }
+
+impl From<ConfigError> for Error {
+    fn from(value: ConfigError) -> Self {
+        Self::ConfigError(value)
+    }
+}
+
+impl From<TokenStoreError> for Error {
+    fn from(value: TokenStoreError) -> Self {
+        Self::TokenStoreError(value)
+    }
+}
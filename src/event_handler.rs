@@ -0,0 +1,215 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use crate::irc::{
+    IRCMessage, LifecycleEvent, Source, UserContext, UserNoticeEvent, WhisperContext,
+};
+use crate::twitch_client::TwitchClient;
+
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult = Result<(), HandlerError>;
+
+/// Callbacks for the messages received by `TwitchClient::run`. Every
+/// callback does nothing by default, so handlers only implement the ones
+/// they need. Errors returned by a callback are passed to `on_error` and
+/// don't stop the client.
+#[async_trait]
+pub trait EventHandler: Send {
+    /// Called for every message before the callback for its kind
+    async fn on_message(
+        &mut self,
+        _client: &mut TwitchClient,
+        _message: &IRCMessage,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    /// Called when the server accepted the login, also after reconnecting
+    async fn on_connected(&mut self, _client: &mut TwitchClient) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_reconnecting(
+        &mut self,
+        _client: &mut TwitchClient,
+        _attempt: u32,
+        _delay: Duration,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_privmsg(
+        &mut self,
+        _client: &mut TwitchClient,
+        _channel: &str,
+        _user_context: &UserContext,
+        _message: &str,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_whisper(
+        &mut self,
+        _client: &mut TwitchClient,
+        _sender: &WhisperContext,
+        _message: &str,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_user_notice(
+        &mut self,
+        _client: &mut TwitchClient,
+        _channel: &str,
+        _user_context: &UserContext,
+        _event: &UserNoticeEvent,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_notice(
+        &mut self,
+        _client: &mut TwitchClient,
+        _channel: Option<&str>,
+        _message: &str,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_join(
+        &mut self,
+        _client: &mut TwitchClient,
+        _channel: &str,
+        _source: &Source,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    async fn on_part(
+        &mut self,
+        _client: &mut TwitchClient,
+        _channel: &str,
+        _source: &Source,
+    ) -> HandlerResult {
+        Ok(())
+    }
+
+    /// Only called when the client doesn't answer PINGs itself
+    async fn on_ping(&mut self, _client: &mut TwitchClient, _message: &str) -> HandlerResult {
+        Ok(())
+    }
+
+    /// Called with errors returned by other callbacks and errors from
+    /// receiving messages
+    async fn on_error(&mut self, _client: &mut TwitchClient, _error: HandlerError) {}
+}
+
+// Calls the callbacks matching the message
+pub async fn dispatch<H: EventHandler>(
+    handler: &mut H,
+    client: &mut TwitchClient,
+    message: &IRCMessage,
+) -> HandlerResult {
+    handler.on_message(client, message).await?;
+
+    match message {
+        IRCMessage::Numbered { number: 1, .. } => handler.on_connected(client).await,
+        IRCMessage::Lifecycle(LifecycleEvent::Reconnecting { attempt, delay }) => {
+            handler.on_reconnecting(client, *attempt, *delay).await
+        }
+        IRCMessage::Privmsg {
+            channel,
+            user_context,
+            message,
+            ..
+        } => {
+            handler
+                .on_privmsg(client, channel, user_context, message)
+                .await
+        }
+        IRCMessage::Whisper {
+            sender, message, ..
+        } => handler.on_whisper(client, sender, message).await,
+        IRCMessage::UserNotice {
+            channel,
+            user_context,
+            event,
+            ..
+        } => {
+            handler
+                .on_user_notice(client, channel, user_context, event)
+                .await
+        }
+        IRCMessage::Notice {
+            channel, message, ..
+        } => handler.on_notice(client, channel.as_deref(), message).await,
+        IRCMessage::Join { channel, source } => handler.on_join(client, channel, source).await,
+        IRCMessage::Part {
+            channel, source, ..
+        } => handler.on_part(client, channel, source).await,
+        IRCMessage::Ping(message) => handler.on_ping(client, message).await,
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credentials::Credentials;
+    use crate::irc::parse_message;
+
+    #[derive(Default)]
+    struct CountingHandler {
+        messages: u32,
+        privmsgs: Vec<(String, String)>,
+    }
+
+    #[async_trait]
+    impl EventHandler for CountingHandler {
+        async fn on_message(
+            &mut self,
+            _client: &mut TwitchClient,
+            _message: &IRCMessage,
+        ) -> HandlerResult {
+            self.messages += 1;
+            Ok(())
+        }
+
+        async fn on_privmsg(
+            &mut self,
+            _client: &mut TwitchClient,
+            channel: &str,
+            _user_context: &UserContext,
+            message: &str,
+        ) -> HandlerResult {
+            self.privmsgs.push((channel.to_owned(), message.to_owned()));
+            Err("handler failed".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let credentials = Credentials {
+            refresh_token: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+        };
+        let mut client = TwitchClient::new(credentials, "bot".to_owned(), true);
+        let mut handler = CountingHandler::default();
+
+        let privmsg = parse_message(
+            "@user-type=;user-id=1;badges=;mod=0;returning-chatter=0;first-msg=0;turbo=0;subscriber=0;display-name=abc \
+            :abc!abc@abc.tmi.twitch.tv PRIVMSG #xyz :HeyGuys",
+        )
+        .unwrap();
+        let ping = parse_message("PING :tmi.twitch.tv").unwrap();
+
+        assert!(dispatch(&mut handler, &mut client, &privmsg).await.is_err());
+        assert!(dispatch(&mut handler, &mut client, &ping).await.is_ok());
+        assert_eq!(handler.messages, 2);
+        assert_eq!(
+            handler.privmsgs,
+            vec![("xyz".to_owned(), "HeyGuys".to_owned())]
+        );
+    }
+}
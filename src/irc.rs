@@ -1,9 +1,10 @@
 use crate::error::MessageParseError;
 use crate::tags;
-use crate::tags::{Badge, Tags, UserType};
+use crate::tags::{Badge, Emote, SubPlan, Tags, UserType};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
 
 lazy_static! {
     // Parses twitch messages into four components:
@@ -14,11 +15,11 @@ lazy_static! {
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Source {
-    nick: Option<String>,
-    host: String,
+    pub nick: Option<String>,
+    pub host: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UserContext {
     pub username: String,
     pub user_type: UserType,
@@ -32,21 +33,185 @@ pub struct UserContext {
     pub badges: Vec<Badge>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct Subscription {
+    pub plan: SubPlan,
+    pub plan_name: String,
+    pub cumulative_months: u32,
+    // Only present when the user chose to share their streak
+    pub streak_months: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Recipient {
+    pub username: String,
+    pub display_name: String,
+    pub user_id: String,
+}
+
+// Sender of a whisper. Whispers aren't tied to a channel, so unlike
+// UserContext there is no mod or subscriber status.
+#[derive(Debug)]
+pub struct WhisperContext {
+    pub username: String,
+    pub user_id: String,
+    pub user_type: UserType,
+    pub color: Option<String>,
+    pub is_turbo: bool,
+    pub badges: Vec<Badge>,
+}
+
+// Event described by the msg-id tag of a USERNOTICE
+#[derive(Debug, PartialEq, Eq)]
+pub enum UserNoticeEvent {
+    Sub(Subscription),
+    Resub(Subscription),
+    SubGift {
+        plan: SubPlan,
+        plan_name: String,
+        gift_months: u32,
+        recipient: Recipient,
+    },
+    SubMysteryGift {
+        plan: SubPlan,
+        gift_count: u32,
+    },
+    GiftPaidUpgrade {
+        gifter_username: String,
+        gifter_display_name: String,
+    },
+    AnonGiftPaidUpgrade,
+    Raid {
+        raider_username: String,
+        raider_display_name: String,
+        viewer_count: u32,
+    },
+    Unraid,
+    Ritual {
+        name: String,
+    },
+    BitsBadgeTier {
+        threshold: u32,
+    },
+    Announcement {
+        color: String,
+    },
+
+    // Matches any msg-id this crate doesn't know about yet
+    Other(String),
+}
+
+// What a CLEARCHAT removed from the channel
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClearChatAction {
+    ClearAll,
+    Ban {
+        username: String,
+        user_id: String,
+    },
+    Timeout {
+        username: String,
+        user_id: String,
+        duration: Duration,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowersOnly {
+    Disabled,
+    // Minimum time a user must have followed the channel before chatting
+    Enabled(Duration),
+}
+
+// Chat settings of a channel. ROOMSTATE messages sent after a setting
+// changes only include that setting, so every field is optional.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RoomState {
+    pub room_id: Option<String>,
+    pub emote_only: Option<bool>,
+    pub followers_only: Option<FollowersOnly>,
+    pub r9k: Option<bool>,
+    pub slow: Option<Duration>,
+    pub subs_only: Option<bool>,
+}
+
+impl RoomState {
+    // Applies the settings present in a (possibly partial) ROOMSTATE
+    pub fn update(&mut self, delta: &RoomState) {
+        if delta.room_id.is_some() {
+            self.room_id.clone_from(&delta.room_id);
+        }
+        self.emote_only = delta.emote_only.or(self.emote_only);
+        self.followers_only = delta.followers_only.or(self.followers_only);
+        self.r9k = delta.r9k.or(self.r9k);
+        self.slow = delta.slow.or(self.slow);
+        self.subs_only = delta.subs_only.or(self.subs_only);
+    }
+}
+
+// State of the authenticated user in a single channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserState {
+    pub username: String,
+    pub user_type: UserType,
+    pub color: Option<String>,
+    pub badges: Vec<Badge>,
+    pub emote_sets: Vec<String>,
+    pub is_mod: bool,
+    pub is_broadcaster: bool,
+    pub is_subscriber: bool,
+    pub is_turbo: bool,
+}
+
+// State of the authenticated user sent once after logging in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalUserState {
+    pub username: String,
+    pub user_id: String,
+    pub user_type: UserType,
+    pub color: Option<String>,
+    pub badges: Vec<Badge>,
+    pub emote_sets: Vec<String>,
+    pub is_turbo: bool,
+}
+
+// Generated by the client rather than received from the server, so the
+// application can follow what happens to the connection
+#[derive(Debug, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    // The connection was lost and a new one will be opened after the delay
+    Reconnecting { attempt: u32, delay: Duration },
+    // A new connection was opened and authentication, capabilities and
+    // joined channels were restored
+    Reconnected,
+}
+
 #[derive(Debug)]
 pub enum IRCMessage {
     Ping(String),
+    Reconnect,
+    Lifecycle(LifecycleEvent),
     Notice {
         source: Source,
+        // Global notices such as login failures aren't sent to a channel
+        channel: Option<String>,
+        msg_id: Option<String>,
         message: String,
     },
+    Join {
+        source: Source,
+        channel: String,
+    },
     Part {
         source: Source,
+        channel: String,
         message: String,
     },
     Privmsg {
         tags: HashMap<String, String>,
         user_context: UserContext,
         source: Source,
+        channel: String,
         message: String,
     },
     Numbered {
@@ -54,6 +219,53 @@ pub enum IRCMessage {
         source: Source,
         message: String,
     },
+    UserNotice {
+        tags: HashMap<String, String>,
+        user_context: UserContext,
+        event: UserNoticeEvent,
+        channel: String,
+        system_message: String,
+        // Text the user attached to the event, e.g. a resub message
+        message: Option<String>,
+        emotes: Vec<Emote>,
+    },
+    ClearChat {
+        tags: HashMap<String, String>,
+        channel: String,
+        action: ClearChatAction,
+        timestamp: SystemTime,
+    },
+    ClearMsg {
+        tags: HashMap<String, String>,
+        channel: String,
+        username: String,
+        target_message_id: String,
+        // Text of the message that was deleted
+        message: String,
+        timestamp: SystemTime,
+    },
+    RoomState {
+        tags: HashMap<String, String>,
+        channel: String,
+        state: RoomState,
+    },
+    UserState {
+        tags: HashMap<String, String>,
+        channel: String,
+        state: UserState,
+    },
+    GlobalUserState {
+        tags: HashMap<String, String>,
+        state: GlobalUserState,
+    },
+    Whisper {
+        tags: HashMap<String, String>,
+        sender: WhisperContext,
+        source: Source,
+        thread_id: String,
+        message_id: String,
+        message: String,
+    },
     Unknown {
         command: String,
     },
@@ -76,6 +288,252 @@ fn parse_source(source_component: &str) -> Source {
     }
 }
 
+// Extracts the channel from a command such as "PRIVMSG #channel"
+fn parse_channel(command: &str) -> Result<String, MessageParseError> {
+    command
+        .split(' ')
+        .nth(1)
+        .and_then(|c| c.strip_prefix('#'))
+        .map(str::to_owned)
+        .ok_or(MessageParseError::MissingChannel(command.to_owned()))
+}
+
+// Returns the display name and ID of the user who sent a message
+fn parse_user_identity(
+    tags: &HashMap<String, String>,
+) -> Result<(String, String), MessageParseError> {
+    let username = tags
+        .get("display-name")
+        .ok_or(MessageParseError::MissingTag("display-name".to_owned()))?;
+    let user_id = tags
+        .get("user-id")
+        .ok_or(MessageParseError::MissingTag("user-id".to_owned()))?;
+
+    Ok((username.to_owned(), user_id.to_owned()))
+}
+
+// Builds the user context from the tags shared by PRIVMSG and USERNOTICE.
+// Chatter history flags are only sent with PRIVMSG, so they default to false.
+fn parse_user_context(tags: &HashMap<String, String>) -> Result<UserContext, MessageParseError> {
+    let badges = tags.try_get_badges()?;
+    let (username, user_id) = parse_user_identity(tags)?;
+
+    Ok(UserContext {
+        username,
+        user_id,
+        user_type: tags.try_get_user_type()?,
+        is_turbo: tags.try_get_bool("turbo")?,
+        is_subscriber: tags.try_get_bool("subscriber")?,
+        is_mod: tags.try_get_bool("mod")?,
+        is_first_message: false,
+        is_returning_chatter: false,
+        is_broadcaster: badges.iter().any(|b| matches!(b, Badge::Broadcaster(_))),
+        badges,
+    })
+}
+
+fn parse_whisper_context(
+    tags: &HashMap<String, String>,
+) -> Result<WhisperContext, MessageParseError> {
+    let (username, user_id) = parse_user_identity(tags)?;
+
+    Ok(WhisperContext {
+        username,
+        user_id,
+        user_type: tags.try_get_user_type()?,
+        color: parse_color(tags),
+        is_turbo: tags.try_get_bool("turbo")?,
+        badges: tags.try_get_badges()?,
+    })
+}
+
+fn parse_subscription(tags: &HashMap<String, String>) -> Result<Subscription, MessageParseError> {
+    let streak_months = if tags.try_get_bool("msg-param-should-share-streak")? {
+        Some(tags.try_get_int("msg-param-streak-months")?)
+    } else {
+        None
+    };
+
+    Ok(Subscription {
+        plan: tags.try_get_sub_plan()?,
+        plan_name: tags.try_get_string("msg-param-sub-plan-name")?,
+        cumulative_months: tags.try_get_int("msg-param-cumulative-months")?,
+        streak_months,
+    })
+}
+
+fn parse_user_notice_event(
+    tags: &HashMap<String, String>,
+) -> Result<UserNoticeEvent, MessageParseError> {
+    let msg_id = tags
+        .get("msg-id")
+        .ok_or(MessageParseError::MissingTag("msg-id".to_owned()))?;
+
+    Ok(match msg_id.as_str() {
+        "sub" => UserNoticeEvent::Sub(parse_subscription(tags)?),
+        "resub" => UserNoticeEvent::Resub(parse_subscription(tags)?),
+        "subgift" => UserNoticeEvent::SubGift {
+            plan: tags.try_get_sub_plan()?,
+            plan_name: tags.try_get_string("msg-param-sub-plan-name")?,
+            gift_months: tags
+                .contains_key("msg-param-gift-months")
+                .then(|| tags.try_get_int("msg-param-gift-months"))
+                .transpose()?
+                .unwrap_or(1),
+            recipient: Recipient {
+                username: tags.try_get_string("msg-param-recipient-user-name")?,
+                display_name: tags.try_get_string("msg-param-recipient-display-name")?,
+                user_id: tags.try_get_string("msg-param-recipient-id")?,
+            },
+        },
+        "submysterygift" => UserNoticeEvent::SubMysteryGift {
+            plan: tags.try_get_sub_plan()?,
+            gift_count: tags.try_get_int("msg-param-mass-gift-count")?,
+        },
+        "giftpaidupgrade" => UserNoticeEvent::GiftPaidUpgrade {
+            gifter_username: tags.try_get_string("msg-param-sender-login")?,
+            gifter_display_name: tags.try_get_string("msg-param-sender-name")?,
+        },
+        "anongiftpaidupgrade" => UserNoticeEvent::AnonGiftPaidUpgrade,
+        "raid" => UserNoticeEvent::Raid {
+            raider_username: tags.try_get_string("msg-param-login")?,
+            raider_display_name: tags.try_get_string("msg-param-displayName")?,
+            viewer_count: tags.try_get_int("msg-param-viewerCount")?,
+        },
+        "unraid" => UserNoticeEvent::Unraid,
+        "ritual" => UserNoticeEvent::Ritual {
+            name: tags.try_get_string("msg-param-ritual-name")?,
+        },
+        "bitsbadgetier" => UserNoticeEvent::BitsBadgeTier {
+            threshold: tags.try_get_int("msg-param-threshold")?,
+        },
+        "announcement" => UserNoticeEvent::Announcement {
+            color: tags.try_get_string("msg-param-color")?,
+        },
+        other => UserNoticeEvent::Other(other.to_owned()),
+    })
+}
+
+fn parse_clear_chat_action(
+    tags: &HashMap<String, String>,
+    parameters: Option<&str>,
+) -> Result<ClearChatAction, MessageParseError> {
+    // Clearing the whole chat doesn't target a user
+    let username = match parameters {
+        Some(username) => username.to_owned(),
+        None => return Ok(ClearChatAction::ClearAll),
+    };
+    let user_id = tags.try_get_string("target-user-id")?;
+
+    if tags.contains_key("ban-duration") {
+        let duration = Duration::from_secs(tags.try_get_int("ban-duration")?.into());
+        return Ok(ClearChatAction::Timeout {
+            username,
+            user_id,
+            duration,
+        });
+    }
+
+    Ok(ClearChatAction::Ban { username, user_id })
+}
+
+fn parse_followers_only(
+    tags: &HashMap<String, String>,
+) -> Result<FollowersOnly, MessageParseError> {
+    let value = tags
+        .get("followers-only")
+        .ok_or(MessageParseError::MissingTag("followers-only".to_owned()))?;
+
+    // -1 disables followers-only mode, otherwise the value is in minutes
+    match value.parse::<i64>() {
+        Ok(-1) => Ok(FollowersOnly::Disabled),
+        Ok(minutes) if minutes >= 0 => Ok(FollowersOnly::Enabled(Duration::from_secs(
+            minutes as u64 * 60,
+        ))),
+        _ => Err(MessageParseError::InvalidIntValue(
+            "followers-only".to_owned(),
+            value.to_owned(),
+        )),
+    }
+}
+
+fn parse_room_state(tags: &HashMap<String, String>) -> Result<RoomState, MessageParseError> {
+    let optional_bool = |key: &str| {
+        tags.contains_key(key)
+            .then(|| tags.try_get_bool(key))
+            .transpose()
+    };
+
+    Ok(RoomState {
+        room_id: tags.get("room-id").cloned(),
+        emote_only: optional_bool("emote-only")?,
+        followers_only: tags
+            .contains_key("followers-only")
+            .then(|| parse_followers_only(tags))
+            .transpose()?,
+        r9k: optional_bool("r9k")?,
+        slow: tags
+            .contains_key("slow")
+            .then(|| tags.try_get_int("slow"))
+            .transpose()?
+            .map(|seconds| Duration::from_secs(seconds.into())),
+        subs_only: optional_bool("subs-only")?,
+    })
+}
+
+fn parse_color(tags: &HashMap<String, String>) -> Option<String> {
+    tags.get("color").filter(|c| !c.is_empty()).cloned()
+}
+
+// Emote set IDs aren't guaranteed to be numeric, so they are kept as sent
+fn parse_emote_sets(tags: &HashMap<String, String>) -> Result<Vec<String>, MessageParseError> {
+    let value = tags
+        .get("emote-sets")
+        .ok_or(MessageParseError::MissingTag("emote-sets".to_owned()))?;
+
+    Ok(value
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(str::to_owned)
+        .collect())
+}
+
+fn parse_user_state(tags: &HashMap<String, String>) -> Result<UserState, MessageParseError> {
+    let badges = tags.try_get_badges()?;
+
+    Ok(UserState {
+        username: tags
+            .get("display-name")
+            .ok_or(MessageParseError::MissingTag("display-name".to_owned()))?
+            .to_owned(),
+        user_type: tags.try_get_user_type()?,
+        color: parse_color(tags),
+        emote_sets: parse_emote_sets(tags)?,
+        is_mod: tags.try_get_bool("mod")?,
+        is_broadcaster: badges.iter().any(|b| matches!(b, Badge::Broadcaster(_))),
+        is_subscriber: tags.try_get_bool("subscriber")?,
+        is_turbo: tags.try_get_bool("turbo")?,
+        badges,
+    })
+}
+
+fn parse_global_user_state(
+    tags: &HashMap<String, String>,
+) -> Result<GlobalUserState, MessageParseError> {
+    Ok(GlobalUserState {
+        username: tags
+            .get("display-name")
+            .ok_or(MessageParseError::MissingTag("display-name".to_owned()))?
+            .to_owned(),
+        user_id: tags.try_get_string("user-id")?,
+        user_type: tags.try_get_user_type()?,
+        color: parse_color(tags),
+        badges: tags.try_get_badges()?,
+        emote_sets: parse_emote_sets(tags)?,
+        is_turbo: tags.try_get_bool("turbo")?,
+    })
+}
+
 pub fn parse_message(line: &str) -> Result<IRCMessage, MessageParseError> {
     let caps = MESSAGE_PATTERN.captures(line).unwrap();
 
@@ -91,42 +549,91 @@ pub fn parse_message(line: &str) -> Result<IRCMessage, MessageParseError> {
     if command.starts_with("PING") {
         let ping_message = parameters.unwrap();
         return Ok(IRCMessage::Ping(ping_message.to_string()));
+    } else if command.starts_with("RECONNECT") {
+        return Ok(IRCMessage::Reconnect);
     } else if command.starts_with("PRIVMSG") {
-        let badges = tags.try_get_badges()?;
-
         let user_context = UserContext {
-            username: tags
-                .get("display-name")
-                .ok_or(MessageParseError::MissingTag("display-name".to_owned()))?
-                .to_owned(),
-            user_id: tags
-                .get("user-id")
-                .ok_or(MessageParseError::MissingTag("user-id".to_owned()))?
-                .to_owned(),
-            user_type: tags.try_get_user_type()?,
-            is_turbo: tags.try_get_bool("turbo")?,
-            is_subscriber: tags.try_get_bool("subscriber")?,
-            is_mod: tags.try_get_bool("mod")?,
             is_first_message: tags.try_get_bool("first-msg")?,
             is_returning_chatter: tags.try_get_bool("returning-chatter")?,
-            is_broadcaster: badges.iter().any(|b| matches!(b, Badge::Broadcaster(_))),
-            badges,
+            ..parse_user_context(&tags)?
         };
 
         return Ok(IRCMessage::Privmsg {
+            channel: parse_channel(command)?,
             message: parameters.unwrap().to_string(),
             source: source.unwrap(),
             user_context,
             tags,
         });
+    } else if command.starts_with("USERNOTICE") {
+        return Ok(IRCMessage::UserNotice {
+            user_context: parse_user_context(&tags)?,
+            event: parse_user_notice_event(&tags)?,
+            channel: parse_channel(command)?,
+            system_message: tags.try_get_string("system-msg")?,
+            message: parameters.map(str::to_owned),
+            emotes: tags.try_get_emotes()?,
+            tags,
+        });
+    } else if command.starts_with("CLEARCHAT") {
+        return Ok(IRCMessage::ClearChat {
+            channel: parse_channel(command)?,
+            action: parse_clear_chat_action(&tags, parameters)?,
+            timestamp: tags.try_get_timestamp()?,
+            tags,
+        });
+    } else if command.starts_with("CLEARMSG") {
+        return Ok(IRCMessage::ClearMsg {
+            channel: parse_channel(command)?,
+            username: tags.try_get_string("login")?,
+            target_message_id: tags.try_get_string("target-msg-id")?,
+            message: parameters.unwrap_or_default().to_string(),
+            timestamp: tags.try_get_timestamp()?,
+            tags,
+        });
+    } else if command.starts_with("ROOMSTATE") {
+        return Ok(IRCMessage::RoomState {
+            channel: parse_channel(command)?,
+            state: parse_room_state(&tags)?,
+            tags,
+        });
+    } else if command.starts_with("USERSTATE") {
+        return Ok(IRCMessage::UserState {
+            channel: parse_channel(command)?,
+            state: parse_user_state(&tags)?,
+            tags,
+        });
+    } else if command.starts_with("GLOBALUSERSTATE") {
+        return Ok(IRCMessage::GlobalUserState {
+            state: parse_global_user_state(&tags)?,
+            tags,
+        });
+    } else if command.starts_with("WHISPER") {
+        return Ok(IRCMessage::Whisper {
+            sender: parse_whisper_context(&tags)?,
+            thread_id: tags.try_get_string("thread-id")?,
+            message_id: tags.try_get_string("message-id")?,
+            message: parameters.unwrap().to_string(),
+            source: source.unwrap(),
+            tags,
+        });
     } else if command.starts_with("NOTICE") {
         return Ok(IRCMessage::Notice {
+            channel: parse_channel(command).ok(),
+            msg_id: tags.get("msg-id").cloned(),
             message: parameters.unwrap().to_string(),
             source: source.unwrap(),
         });
+    } else if command.starts_with("JOIN") {
+        return Ok(IRCMessage::Join {
+            channel: parse_channel(command)?,
+            source: source.unwrap(),
+        });
     } else if command.starts_with("PART") {
         return Ok(IRCMessage::Part {
-            message: parameters.unwrap().to_string(),
+            channel: parse_channel(command)?,
+            // Twitch doesn't send a part message
+            message: parameters.unwrap_or_default().to_string(),
             source: source.unwrap(),
         });
     } else if let Ok(number) = command.split(' ').next().unwrap_or("").parse::<u32>() {
@@ -143,6 +650,7 @@ pub fn parse_message(line: &str) -> Result<IRCMessage, MessageParseError> {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -223,7 +731,13 @@ mod tests {
             .unwrap();
 
         assert!(matches!(actual, IRCMessage::Privmsg { .. }));
-        if let IRCMessage::Privmsg { user_context, .. } = actual {
+        if let IRCMessage::Privmsg {
+            user_context,
+            channel,
+            ..
+        } = actual
+        {
+            assert_eq!(channel, "xyz".to_string());
             assert_eq!(user_context.username, "abc".to_string());
             assert_eq!(user_context.user_id, "1".to_string());
             assert_eq!(user_context.is_returning_chatter, true);
@@ -232,4 +746,391 @@ mod tests {
             assert_eq!(user_context.is_subscriber, true);
         }
     }
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(parse_channel("USERNOTICE #xyz").unwrap(), "xyz");
+        assert!(parse_channel("USERNOTICE").is_err());
+    }
+
+    #[test]
+    fn test_resub() {
+        let actual = parse_message(
+            "@badge-info=subscriber/8;badges=subscriber/6;color=#9ACD32;display-name=abc;emotes=25:0-4;id=1;login=abc;mod=0;\
+            msg-id=resub;msg-param-cumulative-months=8;msg-param-should-share-streak=1;msg-param-streak-months=3;\
+            msg-param-sub-plan-name=Channel\\sSubscription;msg-param-sub-plan=Prime;room-id=2;subscriber=1;\
+            system-msg=abc\\ssubscribed\\swith\\sPrime.;tmi-sent-ts=1;user-id=1;user-type=;turbo=0 \
+            :tmi.twitch.tv USERNOTICE #xyz :Kappa great stream"
+        )
+            .unwrap();
+
+        assert!(matches!(actual, IRCMessage::UserNotice { .. }));
+        if let IRCMessage::UserNotice {
+            user_context,
+            event,
+            channel,
+            system_message,
+            message,
+            emotes,
+            ..
+        } = actual
+        {
+            assert_eq!(user_context.username, "abc".to_string());
+            assert!(user_context.is_subscriber);
+            assert_eq!(channel, "xyz".to_string());
+            assert_eq!(system_message, "abc subscribed with Prime.".to_string());
+            assert_eq!(message, Some("Kappa great stream".to_string()));
+            assert_eq!(emotes.len(), 1);
+            assert_eq!(
+                event,
+                UserNoticeEvent::Resub(Subscription {
+                    plan: SubPlan::Prime,
+                    plan_name: "Channel Subscription".to_string(),
+                    cumulative_months: 8,
+                    streak_months: Some(3),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_sub_gift() {
+        let actual = parse_message(
+            "@badge-info=;badges=;color=;display-name=abc;emotes=;id=1;login=abc;mod=0;msg-id=subgift;\
+            msg-param-months=1;msg-param-recipient-display-name=Def;msg-param-recipient-id=2;\
+            msg-param-recipient-user-name=def;msg-param-sub-plan-name=Channel\\sSubscription;\
+            msg-param-sub-plan=2000;room-id=3;subscriber=0;system-msg=abc\\sgifted\\sa\\ssub!;\
+            tmi-sent-ts=1;user-id=1;user-type=;turbo=0 :tmi.twitch.tv USERNOTICE #xyz"
+        )
+            .unwrap();
+
+        assert!(matches!(actual, IRCMessage::UserNotice { .. }));
+        if let IRCMessage::UserNotice { event, message, .. } = actual {
+            assert_eq!(message, None);
+            assert_eq!(
+                event,
+                UserNoticeEvent::SubGift {
+                    plan: SubPlan::Tier2,
+                    plan_name: "Channel Subscription".to_string(),
+                    gift_months: 1,
+                    recipient: Recipient {
+                        username: "def".to_string(),
+                        display_name: "Def".to_string(),
+                        user_id: "2".to_string(),
+                    },
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_sub_gift_invalid_months() {
+        let actual = parse_message(
+            "@badge-info=;badges=;color=;display-name=abc;emotes=;id=1;login=abc;mod=0;msg-id=subgift;\
+            msg-param-gift-months=x;msg-param-recipient-display-name=Def;msg-param-recipient-id=2;\
+            msg-param-recipient-user-name=def;msg-param-sub-plan-name=Channel\\sSubscription;\
+            msg-param-sub-plan=2000;room-id=3;subscriber=0;system-msg=abc\\sgifted\\sa\\ssub!;\
+            tmi-sent-ts=1;user-id=1;user-type=;turbo=0 :tmi.twitch.tv USERNOTICE #xyz"
+        );
+
+        assert!(matches!(
+            actual,
+            Err(MessageParseError::InvalidIntValue(tag, _)) if tag == "msg-param-gift-months"
+        ));
+    }
+
+    #[test]
+    fn test_raid() {
+        let actual = parse_message(
+            "@badge-info=;badges=turbo/1;color=;display-name=abc;emotes=;id=1;login=abc;mod=0;msg-id=raid;\
+            msg-param-displayName=Abc;msg-param-login=abc;msg-param-viewerCount=15;room-id=2;subscriber=0;\
+            system-msg=15\\sraiders\\sfrom\\sAbc\\shave\\sjoined!;tmi-sent-ts=1;user-id=1;user-type=;turbo=1 \
+            :tmi.twitch.tv USERNOTICE #xyz"
+        )
+            .unwrap();
+
+        assert!(matches!(actual, IRCMessage::UserNotice { .. }));
+        if let IRCMessage::UserNotice { event, .. } = actual {
+            assert_eq!(
+                event,
+                UserNoticeEvent::Raid {
+                    raider_username: "abc".to_string(),
+                    raider_display_name: "Abc".to_string(),
+                    viewer_count: 15,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_clear_chat_timeout() {
+        let actual = parse_message(
+            "@ban-duration=350;room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642719320727 \
+            :tmi.twitch.tv CLEARCHAT #xyz :abc",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::ClearChat { .. }));
+        if let IRCMessage::ClearChat {
+            channel,
+            action,
+            timestamp,
+            ..
+        } = actual
+        {
+            assert_eq!(channel, "xyz".to_string());
+            assert_eq!(
+                action,
+                ClearChatAction::Timeout {
+                    username: "abc".to_string(),
+                    user_id: "87654321".to_string(),
+                    duration: Duration::from_secs(350),
+                }
+            );
+            assert_eq!(
+                timestamp,
+                std::time::UNIX_EPOCH + Duration::from_millis(1642719320727)
+            );
+        }
+    }
+
+    #[test]
+    fn test_clear_chat_ban() {
+        let actual = parse_message(
+            "@room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642715756806 \
+            :tmi.twitch.tv CLEARCHAT #xyz :abc",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::ClearChat { .. }));
+        if let IRCMessage::ClearChat { action, .. } = actual {
+            assert_eq!(
+                action,
+                ClearChatAction::Ban {
+                    username: "abc".to_string(),
+                    user_id: "87654321".to_string(),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_clear_chat_all() {
+        let actual = parse_message(
+            "@room-id=12345678;tmi-sent-ts=1642715695392 :tmi.twitch.tv CLEARCHAT #xyz",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::ClearChat { .. }));
+        if let IRCMessage::ClearChat { action, .. } = actual {
+            assert_eq!(action, ClearChatAction::ClearAll);
+        }
+    }
+
+    #[test]
+    fn test_clear_msg() {
+        let actual = parse_message(
+            "@login=abc;room-id=;target-msg-id=94e6c7ff-bf98-4faa-af5d-7ad633a158a9;tmi-sent-ts=1642720582342 \
+            :tmi.twitch.tv CLEARMSG #xyz :what a great day",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::ClearMsg { .. }));
+        if let IRCMessage::ClearMsg {
+            username,
+            target_message_id,
+            message,
+            ..
+        } = actual
+        {
+            assert_eq!(username, "abc".to_string());
+            assert_eq!(
+                target_message_id,
+                "94e6c7ff-bf98-4faa-af5d-7ad633a158a9".to_string()
+            );
+            assert_eq!(message, "what a great day".to_string());
+        }
+    }
+
+    #[test]
+    fn test_room_state() {
+        let actual = parse_message(
+            "@emote-only=0;followers-only=10;r9k=0;room-id=12345678;slow=30;subs-only=1 \
+            :tmi.twitch.tv ROOMSTATE #xyz",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::RoomState { .. }));
+        if let IRCMessage::RoomState { channel, state, .. } = actual {
+            assert_eq!(channel, "xyz".to_string());
+            assert_eq!(
+                state,
+                RoomState {
+                    room_id: Some("12345678".to_string()),
+                    emote_only: Some(false),
+                    followers_only: Some(FollowersOnly::Enabled(Duration::from_secs(600))),
+                    r9k: Some(false),
+                    slow: Some(Duration::from_secs(30)),
+                    subs_only: Some(true),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_room_state_update() {
+        let mut state = match parse_message(
+            "@emote-only=0;followers-only=-1;r9k=0;room-id=12345678;slow=0;subs-only=0 \
+            :tmi.twitch.tv ROOMSTATE #xyz",
+        )
+        .unwrap()
+        {
+            IRCMessage::RoomState { state, .. } => state,
+            other => panic!("expected ROOMSTATE, got {other:?}"),
+        };
+
+        let delta = match parse_message("@room-id=12345678;slow=10 :tmi.twitch.tv ROOMSTATE #xyz")
+            .unwrap()
+        {
+            IRCMessage::RoomState { state, .. } => state,
+            other => panic!("expected ROOMSTATE, got {other:?}"),
+        };
+
+        state.update(&delta);
+
+        assert_eq!(state.slow, Some(Duration::from_secs(10)));
+        assert_eq!(state.followers_only, Some(FollowersOnly::Disabled));
+        assert_eq!(state.subs_only, Some(false));
+    }
+
+    #[test]
+    fn test_user_state() {
+        let actual = parse_message(
+            "@badge-info=;badges=moderator/1;color=#FF0000;display-name=abc;emote-sets=0,33,50,a1b2c3d4-e5f6;\
+            mod=1;subscriber=0;turbo=0;user-type= :tmi.twitch.tv USERSTATE #xyz",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::UserState { .. }));
+        if let IRCMessage::UserState { channel, state, .. } = actual {
+            assert_eq!(channel, "xyz".to_string());
+            assert!(state.is_mod);
+            assert!(!state.is_broadcaster);
+            assert_eq!(state.color, Some("#FF0000".to_string()));
+            assert_eq!(state.badges, vec![Badge::Moderator(1)]);
+            assert_eq!(state.emote_sets, vec!["0", "33", "50", "a1b2c3d4-e5f6"]);
+        }
+    }
+
+    #[test]
+    fn test_global_user_state() {
+        let actual = parse_message(
+            "@badge-info=;badges=;color=;display-name=abc;emote-sets=0,300374282;turbo=0;\
+            user-id=12345678;user-type= :tmi.twitch.tv GLOBALUSERSTATE",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::GlobalUserState { .. }));
+        if let IRCMessage::GlobalUserState { state, .. } = actual {
+            assert_eq!(state.username, "abc".to_string());
+            assert_eq!(state.user_id, "12345678".to_string());
+            assert_eq!(state.color, None);
+            assert_eq!(state.emote_sets, vec!["0", "300374282"]);
+        }
+    }
+
+    #[test]
+    fn test_whisper() {
+        let actual = parse_message(
+            "@badges=staff/1,bits-charity/1;color=#8A2BE2;display-name=abc;emotes=;message-id=306;\
+            thread-id=12345678_87654321;turbo=0;user-id=87654321;user-type=staff \
+            :abc!abc@abc.tmi.twitch.tv WHISPER bot :hello",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::Whisper { .. }));
+        if let IRCMessage::Whisper {
+            sender,
+            thread_id,
+            message_id,
+            message,
+            ..
+        } = actual
+        {
+            assert_eq!(sender.username, "abc".to_string());
+            assert_eq!(sender.user_id, "87654321".to_string());
+            assert_eq!(sender.user_type, UserType::Staff);
+            assert_eq!(
+                sender.badges,
+                vec![Badge::Staff(1), Badge::Other("bits-charity".to_owned(), 1)]
+            );
+            assert_eq!(thread_id, "12345678_87654321".to_string());
+            assert_eq!(message_id, "306".to_string());
+            assert_eq!(message, "hello".to_string());
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let actual = parse_message(":tmi.twitch.tv RECONNECT").unwrap();
+
+        assert!(matches!(actual, IRCMessage::Reconnect));
+    }
+
+    #[test]
+    fn test_join() {
+        let actual = parse_message(":abc!abc@abc.tmi.twitch.tv JOIN #xyz").unwrap();
+
+        assert!(matches!(actual, IRCMessage::Join { .. }));
+        if let IRCMessage::Join { source, channel } = actual {
+            assert_eq!(source.nick, Some("abc".to_string()));
+            assert_eq!(channel, "xyz".to_string());
+        }
+    }
+
+    #[test]
+    fn test_channel_notice() {
+        let actual = parse_message(
+            "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #xyz \
+            :This channel does not exist or has been suspended.",
+        )
+        .unwrap();
+
+        assert!(matches!(actual, IRCMessage::Notice { .. }));
+        if let IRCMessage::Notice {
+            channel, msg_id, ..
+        } = actual
+        {
+            assert_eq!(channel, Some("xyz".to_string()));
+            assert_eq!(msg_id, Some("msg_channel_suspended".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_global_notice() {
+        let actual = parse_message(":tmi.twitch.tv NOTICE * :Login authentication failed").unwrap();
+
+        assert!(matches!(actual, IRCMessage::Notice { .. }));
+        if let IRCMessage::Notice {
+            channel, message, ..
+        } = actual
+        {
+            assert_eq!(channel, None);
+            assert_eq!(message, "Login authentication failed".to_string());
+        }
+    }
+
+    #[test]
+    fn test_part() {
+        let actual = parse_message(":abc!abc@abc.tmi.twitch.tv PART #xyz").unwrap();
+
+        assert!(matches!(actual, IRCMessage::Part { .. }));
+        if let IRCMessage::Part {
+            source, channel, ..
+        } = actual
+        {
+            assert_eq!(source.nick, Some("abc".to_string()));
+            assert_eq!(channel, "xyz".to_string());
+        }
+    }
 }
@@ -1,6 +1,15 @@
-mod auth;
+pub mod auth;
+pub mod builder;
+pub mod commands;
 pub mod credentials;
 pub mod error;
+pub mod event_handler;
 pub mod irc;
-mod tags;
+pub mod message_stream;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod scope;
+pub mod tags;
+pub mod token_store;
+pub mod transport;
 pub mod twitch_client;
@@ -0,0 +1,162 @@
+use futures_util::future::BoxFuture;
+use futures_util::{ready, Stream};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::error::Error;
+use crate::irc::IRCMessage;
+use crate::twitch_client::TwitchClient;
+
+type NextMessage = (TwitchClient, Option<Result<IRCMessage, Error>>);
+
+/// Stream of the messages returned by `TwitchClient::next`, created with
+/// `TwitchClient::into_stream`. While waiting for a message the stream
+/// owns the client, in between messages it can be borrowed again with
+/// `client_mut` to send replies.
+pub struct MessageStream {
+    client: Option<TwitchClient>,
+    next_message: Option<BoxFuture<'static, NextMessage>>,
+}
+
+impl MessageStream {
+    pub fn new(client: TwitchClient) -> Self {
+        MessageStream {
+            client: Some(client),
+            next_message: None,
+        }
+    }
+
+    /// Returns the client, or None while the stream is waiting for the
+    /// next message
+    pub fn client(&self) -> Option<&TwitchClient> {
+        self.client.as_ref()
+    }
+
+    /// Returns the client between messages. While a poll is in flight the
+    /// stream owns the client and this returns None, also after a `next`
+    /// future was dropped before it completed, until the stream is polled
+    /// to the next message.
+    pub fn client_mut(&mut self) -> Option<&mut TwitchClient> {
+        self.client.as_mut()
+    }
+
+    /// Gives the client back, or None if the stream is still waiting for a
+    /// message
+    pub fn into_client(self) -> Option<TwitchClient> {
+        self.client
+    }
+}
+
+impl Stream for MessageStream {
+    type Item = Result<IRCMessage, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if this.next_message.is_none() {
+            let mut client = match this.client.take() {
+                Some(client) => client,
+                None => return Poll::Ready(None),
+            };
+
+            this.next_message = Some(Box::pin(async move {
+                let message = client.next().await;
+                (client, message)
+            }));
+        }
+
+        let (client, message) = ready!(this
+            .next_message
+            .as_mut()
+            .expect("next message future was just created")
+            .as_mut()
+            .poll(cx));
+
+        this.next_message = None;
+        this.client = Some(client);
+        Poll::Ready(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::twitch_client::tests::mock_client;
+    use futures_util::{FutureExt, StreamExt};
+
+    const MESSAGES: &str = ":tmi.twitch.tv NOTICE * :a\r\nPING :tmi.twitch.tv\r\n\
+        :tmi.twitch.tv NOTICE #xyz :b\r\n";
+
+    #[tokio::test]
+    async fn test_stream_yields_same_items_as_next() {
+        let (mut client, server) = mock_client();
+        server.send(MESSAGES);
+        let mut expected = Vec::new();
+        for _ in 0..2 {
+            expected.push(format!("{:?}", client.next().await.unwrap().unwrap()));
+        }
+
+        let (client, server) = mock_client();
+        server.send(MESSAGES);
+        let stream = client.into_stream();
+        let actual = stream
+            .take(2)
+            .map(|message| format!("{:?}", message.unwrap()))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_after_shutdown() {
+        let (client, server) = mock_client();
+        let mut stream = client.into_stream();
+
+        // The client is away while a message is awaited
+        assert!(stream.next().now_or_never().is_none());
+        assert!(stream.client_mut().is_none());
+
+        server.send(":tmi.twitch.tv NOTICE * :a\r\n");
+        assert!(stream.next().await.unwrap().is_ok());
+        stream.client_mut().unwrap().shutdown().await.unwrap();
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_writer_commands_flow_while_polled() {
+        let (client, mut server) = mock_client();
+        let (writer, mut stream) = client.split();
+        let next = tokio::spawn(async move { stream.next().await });
+
+        writer.privmsg("xyz", "hello").await.unwrap();
+        assert_eq!(server.line().await, "PRIVMSG #xyz :hello");
+
+        server.send(":tmi.twitch.tv NOTICE * :a\r\n");
+        assert!(matches!(
+            next.await.unwrap(),
+            Some(Ok(IRCMessage::Notice { .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_writer_used_by_the_polling_task() {
+        let (client, mut server) = mock_client();
+        let (writer, mut stream) = client.split();
+        server.send(":tmi.twitch.tv NOTICE * :a\r\n:tmi.twitch.tv NOTICE * :b\r\n");
+
+        // Replying from the loop that drives the stream doesn't block it
+        let mut handles = Vec::new();
+        for _ in 0..2 {
+            assert!(stream.next().await.unwrap().is_ok());
+            handles.push(writer.privmsg("xyz", "reply"));
+        }
+        let next = tokio::spawn(async move { stream.next().await });
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(server.lines(), vec!["PRIVMSG #xyz :reply"; 2]);
+        next.abort();
+    }
+}
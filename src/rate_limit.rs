@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limit {
+    pub messages: u32,
+    pub period: Duration,
+}
+
+/// Chat message and JOIN limits of the bot account. Twitch allows more
+/// messages in channels where the bot is a moderator or broadcaster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    // Applies to channels where the bot isn't a moderator
+    pub normal: Limit,
+    // Applies to all messages of the account together, since Twitch counts
+    // them per user rather than per channel
+    pub moderator: Limit,
+    // Counts joined channels rather than JOIN commands
+    pub joins: Limit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            normal: Limit {
+                messages: 20,
+                period: Duration::from_secs(30),
+            },
+            moderator: Limit {
+                messages: 100,
+                period: Duration::from_secs(30),
+            },
+            joins: Limit {
+                messages: 20,
+                period: Duration::from_secs(10),
+            },
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn verified_bot() -> Self {
+        let limit = Limit {
+            messages: 7500,
+            period: Duration::from_secs(30),
+        };
+
+        RateLimits {
+            normal: limit,
+            moderator: limit,
+            joins: Limit {
+                messages: 2000,
+                period: Duration::from_secs(10),
+            },
+        }
+    }
+}
+
+// Times of the last sends, oldest first. A send is allowed while fewer
+// than `messages` sends happened in the period before it, so no period
+// ever holds more than `messages` sends.
+#[derive(Debug, Clone)]
+struct SlidingWindow {
+    limit: Limit,
+    sent: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    fn new(limit: Limit) -> Self {
+        SlidingWindow {
+            limit,
+            sent: VecDeque::new(),
+        }
+    }
+
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        if !self.time_until_available(now).is_zero() {
+            return false;
+        }
+
+        self.acquire(now);
+        true
+    }
+
+    fn acquire(&mut self, now: Instant) {
+        self.sent.push_back(now);
+        // Older sends don't matter once there are enough newer ones
+        while self.sent.len() > self.limit.messages as usize {
+            self.sent.pop_front();
+        }
+    }
+
+    fn time_until_available(&self, now: Instant) -> Duration {
+        let messages = self.limit.messages as usize;
+        if self.sent.len() < messages {
+            return Duration::ZERO;
+        }
+
+        // The oldest of the last `messages` sends has to leave the period
+        let oldest = self.sent[self.sent.len() - messages];
+        (oldest + self.limit.period).saturating_duration_since(now)
+    }
+}
+
+/// Sliding windows for the normal and moderator chat limits and the JOIN
+/// limit. Every message counts towards the moderator limit, messages to
+/// channels where the bot isn't a moderator also towards the normal limit.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    normal: SlidingWindow,
+    moderator: SlidingWindow,
+    joins: SlidingWindow,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            normal: SlidingWindow::new(limits.normal),
+            moderator: SlidingWindow::new(limits.moderator),
+            joins: SlidingWindow::new(limits.joins),
+        }
+    }
+
+    /// Counts a message if the limits allow sending it now
+    pub fn try_acquire(&mut self, is_moderator: bool, now: Instant) -> bool {
+        if !self.time_until_available(is_moderator, now).is_zero() {
+            return false;
+        }
+
+        self.moderator.acquire(now);
+        if !is_moderator {
+            self.normal.acquire(now);
+        }
+        true
+    }
+
+    pub fn time_until_available(&self, is_moderator: bool, now: Instant) -> Duration {
+        let shared = self.moderator.time_until_available(now);
+        if is_moderator {
+            return shared;
+        }
+
+        shared.max(self.normal.time_until_available(now))
+    }
+
+    /// Counts joining one channel if the JOIN limit allows it now
+    pub fn try_acquire_join(&mut self, now: Instant) -> bool {
+        self.joins.try_acquire(now)
+    }
+
+    pub fn time_until_join_available(&self, now: Instant) -> Duration {
+        self.joins.time_until_available(now)
+    }
+
+    /// Estimates how long it takes until every message in the queue has
+    /// been sent. Each item is whether the message goes to a channel where
+    /// the bot is a moderator.
+    pub fn wait_time(&self, queue: impl IntoIterator<Item = bool>, now: Instant) -> Duration {
+        let mut limiter = self.clone();
+        let mut sent_at = now;
+
+        for is_moderator in queue {
+            sent_at += limiter.time_until_available(is_moderator, sent_at);
+            limiter.try_acquire(is_moderator, sent_at);
+        }
+
+        sent_at - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> RateLimits {
+        RateLimits {
+            normal: Limit {
+                messages: 2,
+                period: Duration::from_secs(10),
+            },
+            moderator: Limit {
+                messages: 4,
+                period: Duration::from_secs(10),
+            },
+            joins: Limit {
+                messages: 1,
+                period: Duration::from_secs(1),
+            },
+        }
+    }
+
+    // Sends as soon as the limiter allows and returns the times of the sends
+    fn send_times(limiter: &mut RateLimiter, is_moderator: bool, count: usize) -> Vec<Instant> {
+        let mut now = Instant::now();
+
+        (0..count)
+            .map(|_| {
+                now += limiter.time_until_available(is_moderator, now);
+                assert!(limiter.try_acquire(is_moderator, now));
+                now
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_window_fills_and_slides() {
+        let mut limiter = RateLimiter::new(limits());
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire(false, now));
+        assert!(limiter.try_acquire(false, now + Duration::from_secs(4)));
+        assert!(!limiter.try_acquire(false, now + Duration::from_secs(5)));
+        assert_eq!(
+            limiter.time_until_available(false, now + Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+        assert!(limiter.try_acquire(false, now + Duration::from_secs(10)));
+        assert_eq!(
+            limiter.time_until_available(false, now + Duration::from_secs(12)),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn test_no_period_exceeds_the_limit() {
+        let limits = RateLimits::default();
+        let holds_limit = |times: &[Instant], limit: Limit| {
+            let messages = limit.messages as usize;
+            times
+                .windows(messages + 1)
+                .all(|w| w[messages] - w[0] >= limit.period)
+        };
+
+        for (is_moderator, limit) in [(false, limits.normal), (true, limits.moderator)] {
+            let times = send_times(&mut RateLimiter::new(limits), is_moderator, 250);
+            assert!(holds_limit(&times, limit));
+        }
+
+        let mut limiter = RateLimiter::new(limits);
+        let mut now = Instant::now();
+        let joins = (0..50)
+            .map(|_| {
+                now += limiter.time_until_join_available(now);
+                assert!(limiter.try_acquire_join(now));
+                now
+            })
+            .collect::<Vec<_>>();
+        assert!(holds_limit(&joins, limits.joins));
+    }
+
+    #[test]
+    fn test_moderator_window_is_shared() {
+        let mut limiter = RateLimiter::new(limits());
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire(false, now));
+        assert!(limiter.try_acquire(false, now));
+        assert!(limiter.try_acquire(true, now));
+        assert_eq!(limiter.time_until_available(true, now), Duration::ZERO);
+
+        // Messages to other channels used up part of the moderator limit
+        assert!(limiter.try_acquire(true, now));
+        assert!(!limiter.try_acquire(true, now));
+        assert_eq!(
+            limiter.time_until_available(true, now),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_normal_messages_wait_for_moderator_limit() {
+        let mut limiter = RateLimiter::new(limits());
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            assert!(limiter.try_acquire(true, now));
+        }
+        assert!(!limiter.try_acquire(false, now));
+        assert_eq!(
+            limiter.time_until_available(false, now),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn test_join_window() {
+        let mut limiter = RateLimiter::new(limits());
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_join(now));
+        assert!(!limiter.try_acquire_join(now));
+        assert_eq!(
+            limiter.time_until_join_available(now),
+            Duration::from_secs(1)
+        );
+        assert!(limiter.try_acquire(false, now));
+    }
+
+    #[test]
+    fn test_wait_time() {
+        let limiter = RateLimiter::new(limits());
+        let now = Instant::now();
+
+        assert_eq!(limiter.wait_time([false, false], now), Duration::ZERO);
+        assert_eq!(
+            limiter.wait_time([false, false, false], now),
+            Duration::from_secs(10)
+        );
+        assert_eq!(limiter.wait_time([false; 5], now), Duration::from_secs(20));
+
+        // Twitch's 20 messages per 30s, the 21st waits for the next period
+        let limiter = RateLimiter::new(RateLimits::default());
+        assert_eq!(limiter.wait_time([false; 20], now), Duration::ZERO);
+        assert_eq!(limiter.wait_time([false; 39], now), Duration::from_secs(30));
+    }
+}
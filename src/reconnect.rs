@@ -0,0 +1,92 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Controls how the client reconnects after the server asks it to or the
+/// socket fails. Attempts are delayed with exponential backoff, with a
+/// random share of the delay removed so that many clients don't reconnect
+/// at the same moment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    // Number of attempts before giving up, 0 disables reconnecting
+    pub max_attempts: u32,
+    // Delay before the first attempt, doubled for every following attempt
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Share of the delay between 0.0 and 1.0 that is randomized
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn disabled() -> Self {
+        ReconnectPolicy {
+            max_attempts: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before the given attempt, starting at 1, without jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        self.base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay)
+    }
+
+    /// Delay before the given attempt, starting at 1, with jitter applied
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+
+        backoff.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max_delay() {
+        let policy = ReconnectPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.0,
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(4), Duration::from_secs(5));
+        assert_eq!(policy.delay(100), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_jitter_stays_within_backoff() {
+        let policy = ReconnectPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+
+        for attempt in 1..10 {
+            let delay = policy.delay(attempt);
+            let backoff = policy.backoff(attempt);
+            assert!(delay <= backoff);
+            assert!(delay >= backoff / 2);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// OAuth scopes of an access token that matter for chat. Scopes without a
+/// variant are kept as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Scope {
+    ChatRead,
+    ChatEdit,
+    WhispersRead,
+    WhispersEdit,
+    UserManageWhispers,
+    UserReadChat,
+    UserWriteChat,
+    UserBot,
+    ChannelBot,
+    ChannelModerate,
+    ModeratorManageBannedUsers,
+    ModeratorManageChatMessages,
+    ModeratorManageChatSettings,
+    ModeratorManageAnnouncements,
+    ModeratorReadChatters,
+    Other(String),
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::ChatRead => "chat:read",
+            Self::ChatEdit => "chat:edit",
+            Self::WhispersRead => "whispers:read",
+            Self::WhispersEdit => "whispers:edit",
+            Self::UserManageWhispers => "user:manage:whispers",
+            Self::UserReadChat => "user:read:chat",
+            Self::UserWriteChat => "user:write:chat",
+            Self::UserBot => "user:bot",
+            Self::ChannelBot => "channel:bot",
+            Self::ChannelModerate => "channel:moderate",
+            Self::ModeratorManageBannedUsers => "moderator:manage:banned_users",
+            Self::ModeratorManageChatMessages => "moderator:manage:chat_messages",
+            Self::ModeratorManageChatSettings => "moderator:manage:chat_settings",
+            Self::ModeratorManageAnnouncements => "moderator:manage:announcements",
+            Self::ModeratorReadChatters => "moderator:read:chatters",
+            Self::Other(scope) => scope,
+        }
+    }
+}
+
+impl From<&str> for Scope {
+    fn from(value: &str) -> Self {
+        match value {
+            "chat:read" => Self::ChatRead,
+            "chat:edit" => Self::ChatEdit,
+            "whispers:read" => Self::WhispersRead,
+            "whispers:edit" => Self::WhispersEdit,
+            "user:manage:whispers" => Self::UserManageWhispers,
+            "user:read:chat" => Self::UserReadChat,
+            "user:write:chat" => Self::UserWriteChat,
+            "user:bot" => Self::UserBot,
+            "channel:bot" => Self::ChannelBot,
+            "channel:moderate" => Self::ChannelModerate,
+            "moderator:manage:banned_users" => Self::ModeratorManageBannedUsers,
+            "moderator:manage:chat_messages" => Self::ModeratorManageChatMessages,
+            "moderator:manage:chat_settings" => Self::ModeratorManageChatSettings,
+            "moderator:manage:announcements" => Self::ModeratorManageAnnouncements,
+            "moderator:read:chatters" => Self::ModeratorReadChatters,
+            _ => Self::Other(value.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Converts scopes as returned by Twitch
+pub fn parse_scopes(scopes: &[String]) -> Vec<Scope> {
+    scopes.iter().map(|s| Scope::from(s.as_str())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_round_trip() {
+        let scopes = parse_scopes(&[
+            "chat:read".to_owned(),
+            "moderator:manage:banned_users".to_owned(),
+            "bits:read".to_owned(),
+        ]);
+
+        assert_eq!(
+            scopes,
+            vec![
+                Scope::ChatRead,
+                Scope::ModeratorManageBannedUsers,
+                Scope::Other("bits:read".to_owned()),
+            ]
+        );
+        assert_eq!(Scope::ChatEdit.to_string(), "chat:edit");
+        assert_eq!(scopes[2].as_str(), "bits:read");
+    }
+}
@@ -1,7 +1,8 @@
 use crate::error::MessageParseError;
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UserType {
     User,
     Admin,
@@ -25,9 +26,9 @@ impl TryFrom<&str> for UserType {
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Emote {
-    id: String,
-    start_position: u32,
-    end_position: u32,
+    pub id: String,
+    pub start_position: u32,
+    pub end_position: u32,
 }
 
 #[derive(Debug)]
@@ -68,7 +69,7 @@ impl TryFrom<&str> for Emote {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Badge {
     Admin(u32),
     Bits(u32),
@@ -77,9 +78,10 @@ pub enum Badge {
     Subscriber(u32),
     Staff(u32),
     Turbo(u32),
+    Vip(u32),
 
-    // Matches any other badge for now
-    Other(u32),
+    // Any other badge, along with its name
+    Other(String, u32),
 }
 
 impl TryFrom<&str> for Badge {
@@ -104,11 +106,58 @@ impl TryFrom<&str> for Badge {
             "subscriber" => Badge::Subscriber(version),
             "staff" => Badge::Staff(version),
             "turbo" => Badge::Turbo(version),
-            _ => Badge::Other(version),
+            "vip" => Badge::Vip(version),
+            other => Badge::Other(other.to_owned(), version),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubPlan {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+}
+
+impl TryFrom<&str> for SubPlan {
+    type Error = MessageParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(match value {
+            "Prime" => SubPlan::Prime,
+            "1000" => SubPlan::Tier1,
+            "2000" => SubPlan::Tier2,
+            "3000" => SubPlan::Tier3,
+            _ => return Err(MessageParseError::InvalidSubPlan(value.to_owned())),
         })
     }
 }
 
+// Reverses the IRCv3 escaping applied to tag values, e.g. `\s` for a space
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
 pub fn parse_tags(tags_component: &str) -> Result<HashMap<String, String>, MessageParseError> {
     tags_component
         .split(';')
@@ -130,6 +179,7 @@ pub fn parse_tags(tags_component: &str) -> Result<HashMap<String, String>, Messa
 }
 
 pub trait Tags {
+    fn try_get_string(&self, key: &str) -> Result<String, MessageParseError>;
     fn try_get_bool(&self, key: &str) -> Result<bool, MessageParseError>;
     fn try_get_int(&self, key: &str) -> Result<u32, MessageParseError>;
     fn try_get_vec_int(&self, key: &str) -> Result<Vec<u32>, MessageParseError>;
@@ -137,9 +187,17 @@ pub trait Tags {
     fn try_get_badges(&self) -> Result<Vec<Badge>, MessageParseError>;
     fn try_get_emotes(&self) -> Result<Vec<Emote>, MessageParseError>;
     fn try_get_user_type(&self) -> Result<UserType, MessageParseError>;
+    fn try_get_sub_plan(&self) -> Result<SubPlan, MessageParseError>;
+    fn try_get_timestamp(&self) -> Result<SystemTime, MessageParseError>;
 }
 
 impl Tags for HashMap<String, String> {
+    fn try_get_string(&self, key: &str) -> Result<String, MessageParseError> {
+        self.get(key)
+            .map(|v| unescape_tag_value(v))
+            .ok_or(MessageParseError::MissingTag(key.to_owned()))
+    }
+
     fn try_get_bool(&self, key: &str) -> Result<bool, MessageParseError> {
         let value = self
             .get(key)
@@ -210,6 +268,28 @@ impl Tags for HashMap<String, String> {
         UserType::try_from(value.as_str())
             .map_err(|_| MessageParseError::InvalidTag(value.to_owned()))
     }
+
+    fn try_get_sub_plan(&self) -> Result<SubPlan, MessageParseError> {
+        let value = self
+            .get("msg-param-sub-plan")
+            .ok_or(MessageParseError::MissingTag(
+                "msg-param-sub-plan".to_owned(),
+            ))?;
+
+        SubPlan::try_from(value.as_str())
+    }
+
+    fn try_get_timestamp(&self) -> Result<SystemTime, MessageParseError> {
+        let value = self
+            .get("tmi-sent-ts")
+            .ok_or(MessageParseError::MissingTag("tmi-sent-ts".to_owned()))?;
+
+        // tmi-sent-ts is the number of milliseconds since the unix epoch
+        value
+            .parse::<u64>()
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+            .map_err(|_| MessageParseError::InvalidTimestamp(value.to_owned()))
+    }
 }
 
 #[cfg(test)]
@@ -235,4 +315,14 @@ mod tests {
             assert_eq!(value, actual.get(key).unwrap())
         }
     }
+
+    #[test]
+    fn test_try_get_string_unescapes() {
+        let tags = parse_tags(r"system-msg=5\sraiders\sfrom\sabc\:\shave\sjoined\\!").unwrap();
+
+        assert_eq!(
+            tags.try_get_string("system-msg").unwrap(),
+            r"5 raiders from abc; have joined\!"
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::error::TokenStoreError;
+
+/// The part of the credentials that Twitch rotates. The client id and
+/// secret stay with the application and are never stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredToken {
+    pub refresh_token: String,
+}
+
+/// Persists the refresh token of a client, so that tokens rotated by
+/// Twitch survive restarts. The client loads the stored token before
+/// refreshing its access token and saves it when Twitch rotated it.
+pub trait TokenStore: Send {
+    /// Returns the stored token, or None if nothing was saved yet
+    fn load(&mut self) -> Result<Option<StoredToken>, TokenStoreError>;
+
+    fn save(&mut self, token: &StoredToken) -> Result<(), TokenStoreError>;
+}
+
+impl<T: TokenStore + ?Sized> TokenStore for Box<T> {
+    fn load(&mut self) -> Result<Option<StoredToken>, TokenStoreError> {
+        (**self).load()
+    }
+
+    fn save(&mut self, token: &StoredToken) -> Result<(), TokenStoreError> {
+        (**self).save(token)
+    }
+}
+
+/// Stores the token as JSON in a file. On Unix the file is only readable
+/// by its owner.
+pub struct JsonFileTokenStore {
+    path: PathBuf,
+}
+
+impl JsonFileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonFileTokenStore { path: path.into() }
+    }
+}
+
+impl TokenStore for JsonFileTokenStore {
+    fn load(&mut self) -> Result<Option<StoredToken>, TokenStoreError> {
+        let json = match fs::read(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(TokenStoreError::Io(e)),
+        };
+
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(TokenStoreError::Json)
+    }
+
+    fn save(&mut self, token: &StoredToken) -> Result<(), TokenStoreError> {
+        let json = serde_json::to_vec_pretty(token).map_err(TokenStoreError::Json)?;
+
+        // Write a copy first so a failed write doesn't lose the old token
+        let temp_path = self.path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&temp_path)
+            .and_then(|mut file| file.write_all(&json))
+            .map_err(TokenStoreError::Io)?;
+        fs::rename(&temp_path, &self.path).map_err(TokenStoreError::Io)
+    }
+}
+
+/// Keeps the token in memory. Clones share the same token, so the
+/// application can keep one to read what the client saved.
+#[derive(Clone, Default)]
+pub struct MemoryTokenStore {
+    token: Arc<Mutex<Option<StoredToken>>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new(token: Option<StoredToken>) -> Self {
+        MemoryTokenStore {
+            token: Arc::new(Mutex::new(token)),
+        }
+    }
+
+    pub fn token(&self) -> Option<StoredToken> {
+        self.token.lock().unwrap().clone()
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&mut self) -> Result<Option<StoredToken>, TokenStoreError> {
+        Ok(self.token())
+    }
+
+    fn save(&mut self, token: &StoredToken) -> Result<(), TokenStoreError> {
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(refresh_token: &str) -> StoredToken {
+        StoredToken {
+            refresh_token: refresh_token.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_json_file_token_store() {
+        let path = std::env::temp_dir().join(format!(
+            "twitch_client_rs_token_store_{}.json",
+            std::process::id()
+        ));
+        let mut store = JsonFileTokenStore::new(&path);
+
+        assert!(store.load().unwrap().is_none());
+        store.save(&token("first")).unwrap();
+        store.save(&token("second")).unwrap();
+        let loaded = store.load().unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        #[cfg(unix)]
+        let mode =
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, Some(token("second")));
+        assert!(!json.contains("client"));
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_memory_token_store_is_shared() {
+        let store = MemoryTokenStore::default();
+        let mut client_store = store.clone();
+
+        client_store.save(&token("rotated")).unwrap();
+        assert_eq!(store.token(), Some(token("rotated")));
+    }
+}
@@ -0,0 +1,285 @@
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_native_tls::{native_tls, TlsStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use crate::error::TransportError;
+
+/// Twitch's websocket endpoint, used by default
+pub const TWITCH_WEBSOCKET_URL: &str = "wss://irc-ws.chat.twitch.tv:443";
+/// Twitch's plain text IRC endpoint
+pub const TWITCH_IRC_URL: &str = "irc://irc.chat.twitch.tv:6667";
+/// Twitch's IRC endpoint over TLS
+pub const TWITCH_IRC_TLS_URL: &str = "ircs://irc.chat.twitch.tv:6697";
+
+const IRC_PORT: u16 = 6667;
+const IRC_TLS_PORT: u16 = 6697;
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// A connection to an IRC server that sends and receives text lines
+#[async_trait]
+pub trait Transport: Send {
+    /// Sends one IRC line, without the trailing line break
+    async fn send_line(&mut self, line: &str) -> Result<(), TransportError>;
+
+    /// Waits for the next text received, which can hold several lines.
+    /// Returns None once the server closed the connection. Cancelling the
+    /// returned future doesn't lose any data.
+    async fn receive(&mut self) -> Option<Result<String, TransportError>>;
+
+    /// Closes the connection
+    async fn close(&mut self) -> Result<(), TransportError>;
+}
+
+/// Whether `connect` knows a transport for the URL scheme
+pub fn is_supported_scheme(scheme: &str) -> bool {
+    matches!(scheme, "ws" | "wss" | "irc" | "ircs")
+}
+
+/// Opens a connection to the URL, picking the transport by its scheme:
+/// `ws`/`wss` for websockets, `irc` for plain TCP and `ircs` for TLS
+pub async fn connect(url: &Url) -> Result<Box<dyn Transport>, TransportError> {
+    match url.scheme() {
+        "ws" | "wss" => Ok(Box::new(WebSocketTransport::connect(url).await?)),
+        "irc" => {
+            let (host, port) = host_and_port(url, IRC_PORT).ok_or_else(|| unsupported(url))?;
+            Ok(Box::new(TcpTransport::connect(host, port).await?))
+        }
+        "ircs" => {
+            let (host, port) = host_and_port(url, IRC_TLS_PORT).ok_or_else(|| unsupported(url))?;
+            Ok(Box::new(TlsTransport::connect(host, port).await?))
+        }
+        _ => Err(unsupported(url)),
+    }
+}
+
+fn host_and_port(url: &Url, default_port: u16) -> Option<(&str, u16)> {
+    Some((url.host_str()?, url.port().unwrap_or(default_port)))
+}
+
+fn unsupported(url: &Url) -> TransportError {
+    TransportError::UnsupportedUrl(url.to_string())
+}
+
+pub struct WebSocketTransport {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+}
+
+impl WebSocketTransport {
+    pub async fn connect(url: &Url) -> Result<Self, TransportError> {
+        let (stream, _) = connect_async(url).await?;
+        Ok(WebSocketTransport { stream })
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    async fn send_line(&mut self, line: &str) -> Result<(), TransportError> {
+        self.stream.send(Message::Text(line.to_owned())).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Option<Result<String, TransportError>> {
+        loop {
+            match self.stream.next().await? {
+                Ok(Message::Text(text)) => return Some(Ok(text)),
+                Ok(Message::Close(_)) => return None,
+                // Websocket control frames are answered by tungstenite
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.stream.close(None).await?;
+        Ok(())
+    }
+}
+
+/// IRC over a byte stream, with lines terminated by CRLF
+pub struct StreamTransport<S> {
+    stream: S,
+    // Bytes received after the last complete line
+    pending: Vec<u8>,
+}
+
+pub type TcpTransport = StreamTransport<TcpStream>;
+pub type TlsTransport = StreamTransport<TlsStream<TcpStream>>;
+
+impl<S> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        StreamTransport {
+            stream,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl TcpTransport {
+    pub async fn connect(host: &str, port: u16) -> Result<Self, TransportError> {
+        let stream = TcpStream::connect((host, port)).await?;
+        Ok(StreamTransport::new(stream))
+    }
+}
+
+impl TlsTransport {
+    pub async fn connect(host: &str, port: u16) -> Result<Self, TransportError> {
+        let stream = TcpStream::connect((host, port)).await?;
+        let connector = native_tls::TlsConnector::new()?;
+        let stream = tokio_native_tls::TlsConnector::from(connector)
+            .connect(host, stream)
+            .await?;
+        Ok(StreamTransport::new(stream))
+    }
+}
+
+#[async_trait]
+impl<S> Transport for StreamTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn send_line(&mut self, line: &str) -> Result<(), TransportError> {
+        self.stream
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Option<Result<String, TransportError>> {
+        let mut buffer = [0; READ_BUFFER_SIZE];
+
+        loop {
+            // Only complete lines are returned, the rest waits for more data
+            if let Some(end) = self.pending.iter().rposition(|b| *b == b'\n') {
+                let lines = self.pending.drain(..=end).collect::<Vec<_>>();
+                return Some(Ok(String::from_utf8_lossy(&lines).into_owned()));
+            }
+
+            match self.stream.read(&mut buffer).await {
+                Ok(0) if self.pending.is_empty() => return None,
+                // The connection was closed in the middle of a line
+                Ok(0) => {
+                    let rest = std::mem::take(&mut self.pending);
+                    return Some(Ok(String::from_utf8_lossy(&rest).into_owned()));
+                }
+                Ok(read) => self.pending.extend_from_slice(&buffer[..read]),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    // Transport whose server side is driven by the test through MockServer
+    pub(crate) struct MockTransport {
+        received: mpsc::UnboundedReceiver<Result<String, TransportError>>,
+        sent: mpsc::UnboundedSender<String>,
+    }
+
+    pub(crate) struct MockServer {
+        received: mpsc::UnboundedSender<Result<String, TransportError>>,
+        sent: mpsc::UnboundedReceiver<String>,
+    }
+
+    // Dropping the server closes the connection
+    pub(crate) fn mock_transport() -> (MockTransport, MockServer) {
+        let (received_sender, received) = mpsc::unbounded_channel();
+        let (sent_sender, sent) = mpsc::unbounded_channel();
+
+        let transport = MockTransport {
+            received,
+            sent: sent_sender,
+        };
+        let server = MockServer {
+            received: received_sender,
+            sent,
+        };
+        (transport, server)
+    }
+
+    impl MockServer {
+        pub(crate) fn send(&self, text: &str) {
+            let _ = self.received.send(Ok(text.to_owned()));
+        }
+
+        pub(crate) fn fail(&self, error: TransportError) {
+            let _ = self.received.send(Err(error));
+        }
+
+        // Makes every following send of the client fail
+        pub(crate) fn reject_sends(&mut self) {
+            self.sent.close();
+        }
+
+        pub(crate) async fn line(&mut self) -> String {
+            self.sent.recv().await.expect("client closed the transport")
+        }
+
+        // Lines the client sent so far
+        pub(crate) fn lines(&mut self) -> Vec<String> {
+            std::iter::from_fn(|| self.sent.try_recv().ok()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn send_line(&mut self, line: &str) -> Result<(), TransportError> {
+            self.sent
+                .send(line.to_owned())
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe).into())
+        }
+
+        async fn receive(&mut self) -> Option<Result<String, TransportError>> {
+            self.received.recv().await
+        }
+
+        async fn close(&mut self) -> Result<(), TransportError> {
+            self.received.close();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_transport_splits_lines() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let mut transport = StreamTransport::new(client);
+
+        transport.send_line("NICK bot").await.unwrap();
+        let mut sent = [0; 10];
+        server.read_exact(&mut sent).await.unwrap();
+        assert_eq!(&sent, b"NICK bot\r\n");
+
+        server.write_all(b"PING :a\r\nPING").await.unwrap();
+        assert_eq!(transport.receive().await.unwrap().unwrap(), "PING :a\r\n");
+
+        server.write_all(b" :b\r\n").await.unwrap();
+        drop(server);
+        assert_eq!(transport.receive().await.unwrap().unwrap(), "PING :b\r\n");
+        assert!(transport.receive().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connect_rejects_unknown_scheme() {
+        let url = Url::parse("http://irc.chat.twitch.tv").unwrap();
+
+        assert!(matches!(
+            connect(&url).await,
+            Err(TransportError::UnsupportedUrl(_))
+        ));
+    }
+}
@@ -1,18 +1,29 @@
-use futures_util::{SinkExt, StreamExt};
-use std::collections::VecDeque;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
-use tokio::net::TcpStream;
-use tokio_tungstenite::{
-    connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream,
-};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch};
+use url::Url;
 
-use crate::auth;
+use crate::auth::{self, AuthClient, TokenValidation, TOKEN_REFRESH_MARGIN};
+use crate::builder::TwitchClientBuilder;
 use crate::credentials::Credentials;
-use crate::error::{ConnectionError, Error, MessageParseError};
+use crate::error::{ConnectionError, Error, MessageParseError, TokenStoreError, TransportError};
+use crate::event_handler::{self, EventHandler, HandlerError};
 use crate::irc;
-use crate::irc::IRCMessage;
+use crate::irc::{GlobalUserState, IRCMessage, LifecycleEvent, RoomState, UserState};
+use crate::message_stream::MessageStream;
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::reconnect::ReconnectPolicy;
+use crate::scope::{self, Scope};
+use crate::token_store::{StoredToken, TokenStore};
+use crate::transport::{self, Transport};
 
 // Defines extra capabilies for the chat bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Capability {
     Commands,
     Memberships,
@@ -29,167 +40,1199 @@ impl fmt::Display for Capability {
     }
 }
 
+// IRC lines are limited to 512 bytes including the trailing CRLF
+const MAX_LINE_LENGTH: usize = 510;
+// Numerics sent once the server accepted the login
+const RPL_WELCOME: u32 = 1;
+const RPL_ENDOFMOTD: u32 = 376;
+
+// NOTICE msg-ids sent when a channel can't be joined
+const JOIN_FAILURE_MSG_IDS: &[&str] = &["msg_channel_suspended", "tos_ban", "msg_room_not_found"];
+
+// Twitch accepts any password from anonymous justinfan logins
+const ANONYMOUS_PASS: &str = "SCHMOOPIIE";
+
+const TOKEN_REFRESH_RETRY_DELAY: Duration = Duration::from_secs(30);
+// Shortest wait before refreshing, for tokens that are already expired
+const MIN_TOKEN_REFRESH_DELAY: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Called with the updated credentials when Twitch rotated the refresh
+/// token, so the application can save it
+pub type TokenRefreshCallback = Box<dyn FnMut(&Credentials) + Send>;
+
+/// Outcome of `TwitchClient::join_many`
+#[derive(Debug, Default)]
+pub struct JoinReport {
+    // Channels the server confirmed by echoing the JOIN
+    pub joined: Vec<String>,
+    // Channels rejected with a NOTICE, along with the notice message. The
+    // NOTICE is recognized by its msg-id, which requires the Tags capability.
+    pub failed: Vec<(String, String)>,
+    // Channels without an answer before the timeout
+    pub unconfirmed: Vec<String>,
+}
+
+// Sent by a TwitchWriter to the client that owns the connection
+enum WriterCommand {
+    Privmsg {
+        channel_name: String,
+        message: String,
+    },
+    Join(String),
+    Part(String),
+}
+
+// Carries the result of a WriterCommand back to the writer that sent it
+type CommandReply = oneshot::Sender<Result<(), Error>>;
+
+/// Result of a command sent through a `TwitchWriter`, resolving once the
+/// client carried out the command. That happens while `next` is awaited,
+/// so awaiting the handle in the task that calls `next` never completes.
+/// Commands the writer refuses, e.g. chat messages of anonymous clients,
+/// resolve right away. Dropping the handle doesn't cancel the command.
+pub struct CommandHandle {
+    state: CommandState,
+}
+
+enum CommandState {
+    Sent(oneshot::Receiver<Result<(), Error>>),
+    // Taken once the handle returned it
+    Refused(Option<Error>),
+}
+
+impl CommandHandle {
+    fn refused(error: impl Into<Error>) -> Self {
+        CommandHandle {
+            state: CommandState::Refused(Some(error.into())),
+        }
+    }
+}
+
+impl Future for CommandHandle {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match &mut self.state {
+            CommandState::Sent(result) => match Pin::new(result).poll(cx) {
+                Poll::Ready(Ok(result)) => Poll::Ready(result),
+                // The reply is dropped if the client is dropped before
+                // getting to the command
+                Poll::Ready(Err(_)) => Poll::Ready(Err(ConnectionError::ClientDropped.into())),
+                Poll::Pending => Poll::Pending,
+            },
+            CommandState::Refused(error) => Poll::Ready(Err(error
+                .take()
+                .expect("CommandHandle polled after completion"))),
+        }
+    }
+}
+
+/// Cloneable handle for sending from other tasks while the client is busy
+/// receiving, created with `TwitchClient::writer` or `TwitchClient::split`.
+/// Commands are queued and carried out by the client while `next` is
+/// awaited, going through the same rate limits as calling the client
+/// directly. Each method returns right away with a `CommandHandle` that can
+/// be awaited for the result, so failures are reported to the writer
+/// rather than by `next`.
+#[derive(Clone)]
+pub struct TwitchWriter {
+    sender: mpsc::UnboundedSender<(WriterCommand, CommandReply)>,
+    // Chat messages are refused right away for anonymous clients or
+    // tokens without the scope
+    is_anonymous: bool,
+    scopes: watch::Receiver<Option<Vec<Scope>>>,
+}
+
+impl TwitchWriter {
+    fn send(&self, command: WriterCommand) -> CommandHandle {
+        let (reply, result) = oneshot::channel();
+        match self.sender.send((command, reply)) {
+            Ok(()) => CommandHandle {
+                state: CommandState::Sent(result),
+            },
+            Err(_) => CommandHandle::refused(ConnectionError::ClientDropped),
+        }
+    }
+
+    /// Queues a chat message, see `TwitchClient::privmsg`
+    pub fn privmsg(&self, channel_name: &str, message: &str) -> CommandHandle {
+        if self.is_anonymous {
+            return CommandHandle::refused(Error::AnonymousReadOnly);
+        }
+        if lacks_scope(self.scopes.borrow().as_deref(), &Scope::ChatEdit) {
+            return CommandHandle::refused(Error::MissingScope(Scope::ChatEdit));
+        }
+
+        self.send(WriterCommand::Privmsg {
+            channel_name: channel_name.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Queues a JOIN, which is sent once the JOIN rate limit allows
+    pub fn join(&self, channel_name: &str) -> CommandHandle {
+        if lacks_scope(self.scopes.borrow().as_deref(), &Scope::ChatRead) {
+            return CommandHandle::refused(Error::MissingScope(Scope::ChatRead));
+        }
+
+        self.send(WriterCommand::Join(channel_name.to_owned()))
+    }
+
+    pub fn part(&self, channel_name: &str) -> CommandHandle {
+        self.send(WriterCommand::Part(channel_name.to_owned()))
+    }
+}
+
+// What woke the client up while it was waiting for a message
+enum Wakeup {
+    Frame(Option<Result<String, TransportError>>),
+    QueueReady,
+    Command(WriterCommand, CommandReply),
+    TokenRefresh,
+    TokenValidation,
+    DeadlinePassed,
+}
+
+enum ConnectionState {
+    Connected,
+    // The next attempt has to be announced before it is made
+    ReconnectScheduled { attempt: u32 },
+    ReconnectPending { attempt: u32, delay: Duration },
+    // Reconnecting was given up on, no more messages will be received
+    Closed,
+}
+
 pub struct TwitchClient {
-    credentials: Credentials,
+    // None for anonymous read-only clients
+    credentials: Option<Credentials>,
     // Stores the access token retrieved from Credentials
     access_token: String,
+    token_expires_at: Option<Instant>,
+    // When the access token is refreshed while receiving messages
+    token_refresh_at: Option<Instant>,
+    token_refresh_callback: Option<TokenRefreshCallback>,
+    token_store: Option<Box<dyn TokenStore>>,
+    // The store is only read before the first refresh, afterwards the
+    // client's refresh token is the latest one even if saving it failed
+    stored_token_loaded: bool,
+    auth_client: AuthClient,
+    // Result of the last validation of the access token
+    token_validation: Option<TokenValidation>,
+    token_validation_interval: Option<Duration>,
+    next_token_validation_at: Option<Instant>,
+    // Scopes of the access token, None until Twitch reported them. Shared
+    // with the writers.
+    scopes: watch::Sender<Option<Vec<Scope>>>,
     nick: String,
-    url: url::Url,
+    url: Url,
     message_buffer: VecDeque<Result<IRCMessage, MessageParseError>>,
-    ws_stream: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    // Failed background refreshes and validations, returned by next
+    background_errors: VecDeque<Error>,
+    transport: Option<Box<dyn Transport>>,
+    connect_timeout: Duration,
+    // How long join_many waits for the server to answer the JOINs
+    join_timeout: Duration,
+    // How long authenticate waits for the server to accept the login
+    auth_timeout: Duration,
+    // Whether a rejected login is retried once with a new access token
+    refresh_on_auth_failure: bool,
+    // Whether shutdown revokes the access token
+    revoke_on_shutdown: bool,
     auto_pong: bool,
+    // Restored after reconnecting
+    capabilities: Vec<Capability>,
+    joined_channels: Vec<String>,
+    reconnect_policy: ReconnectPolicy,
+    connection_state: ConnectionState,
+    // Set when the server rejects the access token, so it gets refreshed
+    // before reconnecting
+    auth_failed: bool,
+    // Set from connecting until the login went through and, when
+    // reconnecting, the session is restored. Queued messages and writer
+    // commands wait until then.
+    session_pending: bool,
+    // Chat messages waiting for the rate limit, as (channel, message)
+    send_queue: VecDeque<(String, String)>,
+    rate_limiter: RateLimiter,
+    // Channels joined through a TwitchWriter waiting for the JOIN limit
+    join_queue: VecDeque<String>,
+    command_sender: mpsc::UnboundedSender<(WriterCommand, CommandReply)>,
+    command_receiver: mpsc::UnboundedReceiver<(WriterCommand, CommandReply)>,
+    // Chat settings of every joined channel, keyed by channel name
+    room_states: HashMap<String, RoomState>,
+    // State of the bot account itself, globally and per joined channel
+    global_user_state: Option<GlobalUserState>,
+    user_states: HashMap<String, UserState>,
+    // Handed out by connect instead of opening a connection
+    #[cfg(test)]
+    mock_transports: VecDeque<Box<dyn Transport>>,
 }
 
 impl TwitchClient {
     pub fn new(credentials: Credentials, nick: String, auto_pong: bool) -> Self {
+        Self::with_credentials(Some(credentials), nick, auto_pong)
+    }
+
+    /// Creates a read-only client that logs in as a random `justinfan`
+    /// user without an access token. Sending chat messages fails with
+    /// `Error::AnonymousReadOnly`.
+    pub fn anonymous(auto_pong: bool) -> Self {
+        Self::with_credentials(None, anonymous_nick(), auto_pong)
+    }
+
+    pub(crate) fn with_credentials(
+        credentials: Option<Credentials>,
+        nick: String,
+        auto_pong: bool,
+    ) -> Self {
+        let (command_sender, command_receiver) = mpsc::unbounded_channel();
+
         TwitchClient {
             nick,
             credentials,
             message_buffer: VecDeque::new(),
+            background_errors: VecDeque::new(),
             access_token: String::new(),
-            url: url::Url::parse("wss://irc-ws.chat.twitch.tv:443").unwrap(),
-            ws_stream: None,
+            token_expires_at: None,
+            token_refresh_at: None,
+            token_refresh_callback: None,
+            token_store: None,
+            stored_token_loaded: false,
+            auth_client: AuthClient::default(),
+            token_validation: None,
+            token_validation_interval: None,
+            next_token_validation_at: None,
+            scopes: watch::Sender::new(None),
+            url: Url::parse(transport::TWITCH_WEBSOCKET_URL).unwrap(),
+            transport: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            auth_timeout: DEFAULT_AUTH_TIMEOUT,
+            refresh_on_auth_failure: false,
+            revoke_on_shutdown: false,
             auto_pong,
+            capabilities: Vec::new(),
+            joined_channels: Vec::new(),
+            reconnect_policy: ReconnectPolicy::default(),
+            connection_state: ConnectionState::Connected,
+            auth_failed: false,
+            session_pending: false,
+            send_queue: VecDeque::new(),
+            rate_limiter: RateLimiter::new(RateLimits::default()),
+            join_queue: VecDeque::new(),
+            command_sender,
+            command_receiver,
+            room_states: HashMap::new(),
+            global_user_state: None,
+            user_states: HashMap::new(),
+            #[cfg(test)]
+            mock_transports: VecDeque::new(),
         }
     }
 
+    /// Starts configuring a client that connects when it is built
+    pub fn builder(credentials: Credentials, nick: &str) -> TwitchClientBuilder {
+        TwitchClientBuilder::new(credentials, nick)
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.credentials.is_none()
+    }
+
+    pub fn nick_name(&self) -> &str {
+        &self.nick
+    }
+
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    /// When the current access token expires, None before the first
+    /// refresh and for anonymous clients
+    pub fn token_expires_at(&self) -> Option<Instant> {
+        self.token_expires_at
+    }
+
+    /// Sets the callback notified when the refresh token was rotated
+    pub fn set_token_refresh_callback(
+        &mut self,
+        callback: impl FnMut(&Credentials) + Send + 'static,
+    ) {
+        self.token_refresh_callback = Some(Box::new(callback));
+    }
+
+    /// Sets where the refresh token is persisted. A stored token takes
+    /// precedence over the one the client was created with. It is loaded
+    /// once, before the next refresh.
+    pub fn set_token_store(&mut self, token_store: impl TokenStore + 'static) {
+        self.token_store = Some(Box::new(token_store));
+        self.stored_token_loaded = false;
+    }
+
+    /// Sets the base URL of the OAuth2 endpoints, Twitch's by default
+    pub fn set_auth_url(&mut self, auth_url: Url) {
+        self.auth_client = AuthClient::new(auth_url);
+    }
+
+    /// Validates the access token on the interval while receiving messages,
+    /// refreshing it when Twitch doesn't accept it anymore. Twitch expects
+    /// apps to validate their tokens hourly. None stops validating.
+    pub fn set_token_validation_interval(&mut self, interval: Option<Duration>) {
+        self.token_validation_interval = interval;
+        self.next_token_validation_at = interval.map(|i| Instant::now() + i);
+    }
+
+    /// Returns the result of the last successful validation
+    pub fn token_validation(&self) -> Option<&TokenValidation> {
+        self.token_validation.as_ref()
+    }
+
+    /// Scopes of the access token as reported by the last refresh or
+    /// validation, None before either happened
+    pub fn scopes(&self) -> Option<Vec<Scope>> {
+        self.scopes.borrow().clone()
+    }
+
+    fn lacks_scope(&self, scope: &Scope) -> bool {
+        lacks_scope(self.scopes.borrow().as_deref(), scope)
+    }
+
+    /// Sets the server to connect to, see `transport::connect` for the
+    /// supported schemes. Takes effect on the next connect.
+    pub fn set_url(&mut self, url: Url) {
+        self.url = url;
+    }
+
+    pub fn set_connect_timeout(&mut self, connect_timeout: Duration) {
+        self.connect_timeout = connect_timeout;
+    }
+
+    pub fn set_join_timeout(&mut self, join_timeout: Duration) {
+        self.join_timeout = join_timeout;
+    }
+
+    pub fn set_auth_timeout(&mut self, auth_timeout: Duration) {
+        self.auth_timeout = auth_timeout;
+    }
+
+    /// When enabled, `authenticate` refreshes the access token, opens a new
+    /// connection and logs in once more if the server rejects the login
+    pub fn set_refresh_on_auth_failure(&mut self, refresh_on_auth_failure: bool) {
+        self.refresh_on_auth_failure = refresh_on_auth_failure;
+    }
+
+    /// When enabled, `shutdown` revokes the access token, so no live token
+    /// is left behind
+    pub fn set_revoke_on_shutdown(&mut self, revoke_on_shutdown: bool) {
+        self.revoke_on_shutdown = revoke_on_shutdown;
+    }
+
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: ReconnectPolicy) {
+        self.reconnect_policy = reconnect_policy;
+    }
+
+    /// Replaces the chat limits, e.g. with `RateLimits::verified_bot()`.
+    /// Messages already sent in the current period are forgotten.
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limiter = RateLimiter::new(rate_limits);
+    }
+
+    /// Fetches a new access token, first loading the refresh token from
+    /// the token store if it wasn't yet, and saves the refresh token if it
+    /// was rotated. Does nothing for anonymous clients.
     pub async fn update_access_token(&mut self) -> Result<(), Error> {
-        self.access_token = auth::refresh_access_token(&self.credentials)
+        if self.is_anonymous() {
+            return Ok(());
+        }
+        self.load_stored_token()?;
+
+        let Some(credentials) = &self.credentials else {
+            return Ok(());
+        };
+        let response = self
+            .auth_client
+            .refresh_access_token(credentials)
             .await
             .map_err(Error::RefreshAccessTokenError)?;
+        self.apply_refreshed_token(response, Instant::now())?;
+        Ok(())
+    }
+
+    // Replaces the configured refresh token with the stored one, unless
+    // that was done already
+    fn load_stored_token(&mut self) -> Result<(), TokenStoreError> {
+        let (Some(token_store), Some(credentials)) = (&mut self.token_store, &mut self.credentials)
+        else {
+            return Ok(());
+        };
+        if self.stored_token_loaded {
+            return Ok(());
+        }
+
+        if let Some(token) = token_store.load()? {
+            credentials.refresh_token = token.refresh_token;
+        }
+        self.stored_token_loaded = true;
+        Ok(())
+    }
+
+    // Stores the new access token and its expiry, and the refresh token if
+    // Twitch rotated it
+    fn apply_refreshed_token(
+        &mut self,
+        response: auth::RefreshTokenResponse,
+        now: Instant,
+    ) -> Result<(), TokenStoreError> {
+        let expires_in = Duration::from_secs(response.expires_in.into());
+        self.access_token = response.access_token;
+        self.scopes
+            .send_replace(Some(scope::parse_scopes(&response.scope)));
+        self.token_expires_at = Some(now + expires_in);
+        self.token_refresh_at = Some(
+            now + expires_in
+                .saturating_sub(TOKEN_REFRESH_MARGIN)
+                .max(expires_in / 2)
+                .max(MIN_TOKEN_REFRESH_DELAY),
+        );
+
+        let Some(credentials) = &mut self.credentials else {
+            return Ok(());
+        };
+        if credentials.refresh_token == response.refresh_token {
+            return Ok(());
+        }
+
+        credentials.refresh_token = response.refresh_token;
+        if let Some(callback) = &mut self.token_refresh_callback {
+            callback(credentials);
+        }
+        if let Some(token_store) = &mut self.token_store {
+            token_store.save(&StoredToken {
+                refresh_token: credentials.refresh_token.clone(),
+            })?;
+        }
+        Ok(())
+    }
+
+    // Refreshes the access token before it expires, so that reconnecting
+    // doesn't have to wait for a new one. Tries again later on failure.
+    async fn refresh_expiring_token(&mut self) -> Result<(), Error> {
+        if let Err(e) = self.update_access_token().await {
+            self.token_refresh_at = Some(Instant::now() + TOKEN_REFRESH_RETRY_DELAY);
+            return Err(e);
+        }
         Ok(())
     }
 
+    /// Asks Twitch about the current access token, keeping the result
+    pub async fn validate_access_token(&mut self) -> Result<TokenValidation, Error> {
+        let validation = self
+            .auth_client
+            .validate_token(&self.access_token)
+            .await
+            .map_err(Error::ValidateTokenError)?;
+
+        let expires_in = Duration::from_secs(validation.expires_in.into());
+        self.token_expires_at = Some(Instant::now() + expires_in);
+        self.scopes
+            .send_replace(Some(scope::parse_scopes(&validation.scopes)));
+        self.token_validation = Some(validation.clone());
+        Ok(validation)
+    }
+
+    // Validates the access token for the background validator, refreshing
+    // it if Twitch rejected it
+    async fn validate_in_background(&mut self) -> Result<(), Error> {
+        self.next_token_validation_at = self.token_validation_interval.map(|i| Instant::now() + i);
+
+        match self.validate_access_token().await {
+            Err(Error::ValidateTokenError(e))
+                if e.status() == Some(reqwest::StatusCode::UNAUTHORIZED) =>
+            {
+                self.update_access_token().await
+            }
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn token_expired(&self, now: Instant) -> bool {
+        self.token_expires_at.is_some_and(|t| t <= now)
+    }
+
     pub async fn connect(&mut self) -> Result<(), Error> {
-        let (ws_stream, _) = connect_async(&self.url)
+        #[cfg(test)]
+        if let Some(transport) = self.mock_transports.pop_front() {
+            self.transport = Some(transport);
+            self.connection_state = ConnectionState::Connected;
+            self.session_pending = true;
+            return Ok(());
+        }
+
+        let transport = tokio::time::timeout(self.connect_timeout, transport::connect(&self.url))
             .await
-            .map_err(ConnectionError::WebsocketConnectionError)?;
+            .map_err(|_| ConnectionError::ConnectTimeout)?
+            .map_err(ConnectionError::ConnectFailure)?;
 
-        self.ws_stream = Some(ws_stream);
+        self.transport = Some(transport);
+        self.connection_state = ConnectionState::Connected;
+        self.session_pending = true;
         Ok(())
     }
 
-    async fn send(&mut self, message: Message) -> Result<(), Error> {
-        self.ws_stream
+    async fn send(&mut self, line: &str) -> Result<(), Error> {
+        self.transport
             .as_mut()
-            .ok_or(ConnectionError::WebsocketNotConnected)?
-            .send(message)
+            .ok_or(ConnectionError::NotConnected)?
+            .send_line(line)
             .await
             .map_err(ConnectionError::SendMessageFailure)?;
         Ok(())
     }
 
+    /// Logs in and waits for the server to accept the login, failing with
+    /// `Error::AuthenticationFailed` if it is rejected. Queued messages are
+    /// only sent once the login went through.
     pub async fn authenticate(&mut self) -> Result<(), Error> {
+        self.log_in().await?;
+        self.session_pending = false;
+        Ok(())
+    }
+
+    async fn log_in(&mut self) -> Result<(), Error> {
+        match self.try_authenticate().await {
+            Err(Error::AuthenticationFailed(_))
+                if self.refresh_on_auth_failure && !self.is_anonymous() =>
+            {
+                // The server closes the connection after a failed login
+                self.update_access_token().await?;
+                self.auth_failed = false;
+                self.transport = None;
+                self.message_buffer.clear();
+                self.connect().await?;
+                self.try_authenticate().await
+            }
+            result => result,
+        }
+    }
+
+    // Sends PASS and NICK, then waits for the welcome message or the NOTICE
+    // rejecting the login
+    async fn try_authenticate(&mut self) -> Result<(), Error> {
         self.pass().await?;
         self.nick().await?;
+
+        let mut failure = None;
+        let answered = self
+            .read_until(self.auth_timeout, |message| match message {
+                IRCMessage::Numbered {
+                    number: RPL_WELCOME | RPL_ENDOFMOTD,
+                    ..
+                } => true,
+                IRCMessage::Notice { message, .. } if is_auth_failure(message) => {
+                    failure = Some(message.clone());
+                    true
+                }
+                _ => false,
+            })
+            .await?;
+
+        if let Some(message) = failure {
+            self.auth_failed = true;
+            return Err(Error::AuthenticationFailed(message));
+        }
+        if !answered {
+            return Err(Error::AuthenticationTimeout);
+        }
+        Ok(())
+    }
+
+    /// Opens a new connection and restores the session: authenticates
+    /// again, requests the same capabilities and re-joins every channel.
+    pub async fn reconnect(&mut self) -> Result<(), Error> {
+        self.transport = None;
+        self.message_buffer.clear();
+
+        if self.auth_failed || self.token_expired(Instant::now()) {
+            self.update_access_token().await?;
+            self.auth_failed = false;
+        }
+
+        self.connect().await?;
+        self.log_in().await?;
+        if !self.capabilities.is_empty() {
+            self.send_cap_req(&self.capabilities.clone()).await?;
+        }
+        // Channels queued by a TwitchWriter are tracked, so they are joined
+        // again below. Anything queued while waiting for the limit stays.
+        self.join_queue.clear();
+        let joined_channels = self.joined_channels.clone();
+        let channel_names = joined_channels
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        self.send_joins(&channel_names).await?;
+        self.session_pending = false;
         Ok(())
     }
 
     pub async fn cap_req(&mut self, capabilities: &[Capability]) -> Result<(), Error> {
+        self.send_cap_req(capabilities).await?;
+        for capability in capabilities {
+            if !self.capabilities.contains(capability) {
+                self.capabilities.push(*capability);
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_cap_req(&mut self, capabilities: &[Capability]) -> Result<(), Error> {
         let cap_str = capabilities
             .iter()
             .map(Capability::to_string)
             .collect::<Vec<_>>()
             .join(" ");
-        self.send(Message::Text(format!("CAP REQ :{}", cap_str)))
-            .await?;
+        self.send(&format!("CAP REQ :{}", cap_str)).await?;
         Ok(())
     }
 
     pub async fn pass(&mut self) -> Result<(), Error> {
-        self.send(Message::Text(format!("PASS oauth:{}", self.access_token)))
-            .await?;
+        let pass = if self.is_anonymous() {
+            ANONYMOUS_PASS.to_owned()
+        } else {
+            format!("oauth:{}", self.access_token)
+        };
+
+        self.send(&format!("PASS {pass}")).await?;
         Ok(())
     }
 
     pub async fn nick(&mut self) -> Result<(), Error> {
-        self.send(Message::Text(format!("NICK {}", self.nick)))
-            .await?;
+        self.send(&format!("NICK {}", self.nick)).await?;
         Ok(())
     }
 
     pub async fn join(&mut self, channel_name: &str) -> Result<(), Error> {
-        self.send(Message::Text(format!("JOIN #{channel_name}")))
-            .await?;
+        if self.lacks_scope(&Scope::ChatRead) {
+            return Err(Error::MissingScope(Scope::ChatRead));
+        }
+
+        self.send_joins(&[channel_name]).await?;
+        self.track_joined_channel(channel_name);
+        Ok(())
+    }
+
+    /// Joins several channels, batching JOINs and pacing them to the JOIN
+    /// rate limit, then waits for the server to confirm or reject each one.
+    /// Messages received in the meantime are still returned by `next`.
+    pub async fn join_many(&mut self, channel_names: &[&str]) -> Result<JoinReport, Error> {
+        if self.lacks_scope(&Scope::ChatRead) {
+            return Err(Error::MissingScope(Scope::ChatRead));
+        }
+
+        self.send_joins(channel_names).await?;
+        for channel_name in channel_names {
+            self.track_joined_channel(channel_name);
+        }
+
+        let nick = self.nick.clone();
+        let mut pending = channel_names
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        let mut report = JoinReport::default();
+
+        self.read_until(self.join_timeout, |message| {
+            let position =
+                |channel: &str| pending.iter().position(|c| c.eq_ignore_ascii_case(channel));
+
+            match message {
+                IRCMessage::Join { source, channel }
+                    if source
+                        .nick
+                        .as_ref()
+                        .is_some_and(|n| n.eq_ignore_ascii_case(&nick)) =>
+                {
+                    if let Some(i) = position(channel) {
+                        report.joined.push(pending.swap_remove(i));
+                    }
+                }
+                IRCMessage::Notice {
+                    channel: Some(channel),
+                    msg_id: Some(msg_id),
+                    message,
+                    ..
+                } if JOIN_FAILURE_MSG_IDS.contains(&msg_id.as_str()) => {
+                    if let Some(i) = position(channel) {
+                        report
+                            .failed
+                            .push((pending.swap_remove(i), message.clone()));
+                    }
+                }
+                _ => {}
+            }
+
+            pending.is_empty()
+        })
+        .await?;
+
+        for (channel_name, _) in &report.failed {
+            self.joined_channels.retain(|c| c != channel_name);
+        }
+        report.unconfirmed = pending;
+        Ok(report)
+    }
+
+    fn track_joined_channel(&mut self, channel_name: &str) {
+        if !self.joined_channels.iter().any(|c| c == channel_name) {
+            self.joined_channels.push(channel_name.to_owned());
+        }
+    }
+
+    // Sends JOINs for the channels, waiting for the JOIN rate limit
+    // whenever it is reached
+    async fn send_joins(&mut self, channel_names: &[&str]) -> Result<(), Error> {
+        let mut ready = Vec::new();
+
+        for channel_name in channel_names {
+            while !self.rate_limiter.try_acquire_join(Instant::now()) {
+                // Send what is ready before waiting for the limit
+                self.send_join_lines(&ready).await?;
+                ready.clear();
+
+                // Keep receiving while waiting so PINGs are answered. JOINs
+                // queued by a TwitchWriter may use up the limit meanwhile.
+                let delay = self.rate_limiter.time_until_join_available(Instant::now());
+                self.read_until(delay, |_| false).await?;
+            }
+            ready.push(*channel_name);
+        }
+
+        self.send_join_lines(&ready).await
+    }
+
+    // Sends JOINs for the channels, putting as many on one line as fit
+    async fn send_join_lines(&mut self, channel_names: &[&str]) -> Result<(), Error> {
+        let mut line = String::new();
+
+        for channel_name in channel_names {
+            if !line.is_empty() && line.len() + channel_name.len() + 2 > MAX_LINE_LENGTH {
+                self.send(&std::mem::take(&mut line)).await?;
+            }
+
+            if line.is_empty() {
+                line.push_str("JOIN ");
+            } else {
+                line.push(',');
+            }
+            line.push('#');
+            line.push_str(channel_name);
+        }
+
+        if !line.is_empty() {
+            self.send(&line).await?;
+        }
         Ok(())
     }
 
     pub async fn pong(&mut self, ping_message: &str) -> Result<(), Error> {
-        self.send(Message::Text(format!("PONG :{ping_message}")))
-            .await?;
+        self.send(&format!("PONG :{ping_message}")).await?;
         Ok(())
     }
 
     pub async fn part(&mut self, channel_name: &str) -> Result<(), Error> {
-        self.send(Message::Text(format!("PART #{channel_name}")))
-            .await?;
+        self.send(&format!("PART #{channel_name}")).await?;
+        self.joined_channels.retain(|c| c != channel_name);
+        self.room_states.remove(channel_name);
+        self.user_states.remove(channel_name);
         Ok(())
     }
 
+    /// Queues a chat message. Messages are sent right away while the rate
+    /// limit allows, the rest are sent in order while `next` is awaited.
     pub async fn privmsg(&mut self, channel_name: &str, message: &str) -> Result<(), Error> {
-        self.send(Message::Text(format!("PRIVMSG #{channel_name} :{message}")))
-            .await?;
+        if self.is_anonymous() {
+            return Err(Error::AnonymousReadOnly);
+        }
+        if self.lacks_scope(&Scope::ChatEdit) {
+            return Err(Error::MissingScope(Scope::ChatEdit));
+        }
+
+        self.send_queue
+            .push_back((channel_name.to_owned(), message.to_owned()));
+        self.flush_send_queue().await
+    }
+
+    // Sends queued chat messages until the rate limit is reached. Messages
+    // are only removed once sent, so they are kept if sending fails or is
+    // cancelled and go out once the connection is back.
+    async fn flush_send_queue(&mut self) -> Result<(), Error> {
+        if self.session_pending {
+            return Ok(());
+        }
+
+        while let Some((channel_name, message)) = self.send_queue.front() {
+            let is_moderator = self.is_moderator(channel_name);
+            if !self.rate_limiter.try_acquire(is_moderator, Instant::now()) {
+                break;
+            }
+
+            let line = format!("PRIVMSG #{channel_name} :{message}");
+            self.send(&line).await?;
+            self.send_queue.pop_front();
+        }
         Ok(())
     }
 
-    async fn get_next_message(&mut self) -> Option<Result<IRCMessage, Error>> {
-        if !self.message_buffer.is_empty() {
-            return Some(
-                self.message_buffer
-                    .pop_front()?
-                    .map_err(Error::MessageParseError),
-            );
+    /// Number of chat messages waiting for the rate limit
+    pub fn send_queue_len(&self) -> usize {
+        self.send_queue.len()
+    }
+
+    /// Estimates how long a message queued now for the channel would wait
+    /// before being sent
+    pub fn send_wait_time(&self, channel_name: &str) -> Duration {
+        let queue = self
+            .send_queue
+            .iter()
+            .map(|(c, _)| c.as_str())
+            .chain([channel_name])
+            .map(|c| self.is_moderator(c));
+
+        self.rate_limiter.wait_time(queue, Instant::now())
+    }
+
+    // Sends JOINs queued by a TwitchWriter until the JOIN limit is reached.
+    // Like chat messages, channels stay queued until their JOIN was sent.
+    async fn flush_join_queue(&mut self) -> Result<(), Error> {
+        if self.session_pending {
+            return Ok(());
         }
 
-        let stream = match self
-            .ws_stream
-            .as_mut()
-            .ok_or(ConnectionError::WebsocketNotConnected)
-        {
-            Ok(s) => s,
-            Err(e) => return Some(Err(Error::from(e))),
-        };
+        let mut count = 0;
+        while count < self.join_queue.len() && self.rate_limiter.try_acquire_join(Instant::now()) {
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+
+        let channel_names = self
+            .join_queue
+            .iter()
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+        let channel_names = channel_names.iter().map(String::as_str).collect::<Vec<_>>();
+        self.send_join_lines(&channel_names).await?;
+        self.join_queue.drain(..count);
+        Ok(())
+    }
+
+    async fn flush_queues(&mut self) -> Result<(), Error> {
+        self.flush_send_queue().await?;
+        self.flush_join_queue().await
+    }
+
+    // Time until the first queued message or JOIN can be sent, if any
+    fn queue_delay(&self) -> Option<Duration> {
+        if self.session_pending {
+            return None;
+        }
+
+        let now = Instant::now();
+        let send_delay = self.send_queue.front().map(|(channel_name, _)| {
+            self.rate_limiter
+                .time_until_available(self.is_moderator(channel_name), now)
+        });
+        let join_delay =
+            (!self.join_queue.is_empty()).then(|| self.rate_limiter.time_until_join_available(now));
+
+        send_delay.into_iter().chain(join_delay).min()
+    }
+
+    async fn handle_writer_command(&mut self, command: WriterCommand) -> Result<(), Error> {
+        match command {
+            WriterCommand::Privmsg {
+                channel_name,
+                message,
+            } => self.privmsg(&channel_name, &message).await,
+            WriterCommand::Join(_) if self.lacks_scope(&Scope::ChatRead) => {
+                Err(Error::MissingScope(Scope::ChatRead))
+            }
+            WriterCommand::Join(channel_name) => {
+                self.track_joined_channel(&channel_name);
+                self.join_queue.push_back(channel_name);
+                Ok(())
+            }
+            WriterCommand::Part(channel_name) => self.part(&channel_name).await,
+        }
+    }
+
+    /// Returns a handle for sending messages from other tasks, or from the
+    /// task awaiting `next`
+    pub fn writer(&self) -> TwitchWriter {
+        TwitchWriter {
+            sender: self.command_sender.clone(),
+            is_anonymous: self.is_anonymous(),
+            scopes: self.scopes.subscribe(),
+        }
+    }
+
+    /// Splits the client into a writer that can be cloned and moved to
+    /// other tasks, and a stream of incoming messages that carries out
+    /// what is sent through the writer
+    pub fn split(self) -> (TwitchWriter, MessageStream) {
+        (self.writer(), self.into_stream())
+    }
+
+    /// Returns the last known chat settings of a joined channel. The state
+    /// is built from the ROOMSTATE messages received, which requires the
+    /// `Commands` capability.
+    pub fn room_state(&self, channel_name: &str) -> Option<&RoomState> {
+        self.room_states.get(channel_name)
+    }
 
-        match stream
-            .next()
-            .await?
-            .map_err(ConnectionError::ReceiveMessageFailure)
-        {
-            Ok(message) => {
-                if message.is_text() {
-                    let text = match message
-                        .to_text()
-                        .map_err(ConnectionError::ReceiveMessageFailure)
-                    {
-                        Ok(t) => t,
-                        Err(e) => return Some(Err(Error::from(e))),
-                    };
-
-                    for line in text.lines() {
-                        self.message_buffer.push_back(irc::parse_message(line));
+    /// Returns the state of the bot account after logging in, as sent by
+    /// GLOBALUSERSTATE.
+    pub fn global_user_state(&self) -> Option<&GlobalUserState> {
+        self.global_user_state.as_ref()
+    }
+
+    /// Returns the state of the bot account in a joined channel, as sent by
+    /// USERSTATE after joining or sending a message.
+    pub fn user_state(&self, channel_name: &str) -> Option<&UserState> {
+        self.user_states.get(channel_name)
+    }
+
+    /// Whether the bot account can moderate the channel. Broadcasters count
+    /// as moderators of their own channel.
+    pub fn is_moderator(&self, channel_name: &str) -> bool {
+        self.user_state(channel_name)
+            .is_some_and(|state| state.is_mod || state.is_broadcaster)
+    }
+
+    // Waits for the next text frame and buffers the messages in it, sending
+    // queued chat messages in the meantime. Returns false once the server
+    // closed the connection. Also returns once the deadline passed, which
+    // is only checked while waiting, so sends and token requests that
+    // already started are never cut off.
+    async fn receive(&mut self, deadline: Option<tokio::time::Instant>) -> Result<bool, Error> {
+        loop {
+            self.flush_queues().await?;
+            let queue_delay = self.queue_delay();
+            let token_refresh_delay = self
+                .token_refresh_at
+                .map(|t| t.saturating_duration_since(Instant::now()));
+            let token_validation_delay = self
+                .next_token_validation_at
+                .filter(|_| !self.is_anonymous())
+                .map(|t| t.saturating_duration_since(Instant::now()));
+
+            let stream = self
+                .transport
+                .as_mut()
+                .ok_or(ConnectionError::NotConnected)?;
+
+            // Wake up to send queued messages, carry out writer commands and
+            // refresh or validate the access token even when nothing is
+            // received
+            let wakeup = tokio::select! {
+                frame = stream.receive() => Wakeup::Frame(frame),
+                _ = sleep_or_pending(queue_delay) => Wakeup::QueueReady,
+                _ = sleep_or_pending(token_refresh_delay) => Wakeup::TokenRefresh,
+                _ = sleep_or_pending(token_validation_delay) => Wakeup::TokenValidation,
+                _ = sleep_until_or_pending(deadline) => Wakeup::DeadlinePassed,
+                Some((command, reply)) = self.command_receiver.recv(), if !self.session_pending => {
+                    Wakeup::Command(command, reply)
+                }
+            };
+
+            let frame = match wakeup {
+                Wakeup::Frame(frame) => frame,
+                Wakeup::QueueReady => continue,
+                // Both are tried again later. The failure is left for next,
+                // so it doesn't abort a login or join waiting in read_until.
+                Wakeup::TokenRefresh => {
+                    if let Err(e) = self.refresh_expiring_token().await {
+                        self.background_errors.push_back(e);
+                        return Ok(true);
+                    }
+                    continue;
+                }
+                Wakeup::TokenValidation => {
+                    if let Err(e) = self.validate_in_background().await {
+                        self.background_errors.push_back(e);
+                        return Ok(true);
                     }
+                    continue;
+                }
+                Wakeup::Command(command, reply) => {
+                    // The handle may have been dropped without waiting for the result
+                    let _ = reply.send(self.handle_writer_command(command).await);
+                    continue;
+                }
+                Wakeup::DeadlinePassed => {
+                    // What became ready at the same time still goes first
+                    self.flush_queues().await?;
+                    return Ok(true);
+                }
+            };
+
+            let text = match frame {
+                Some(frame) => frame.map_err(ConnectionError::ReceiveMessageFailure)?,
+                None => return Ok(false),
+            };
 
-                    return Some(
-                        self.message_buffer
-                            .pop_front()?
-                            .map_err(Error::MessageParseError),
-                    );
+            let buffered = self.message_buffer.len();
+            for line in text.lines().filter(|line| !line.is_empty()) {
+                self.message_buffer.push_back(irc::parse_message(line));
+            }
+
+            if self.message_buffer.len() > buffered {
+                return Ok(true);
+            }
+        }
+    }
+
+    // Receives messages until `done` returns true for one of them or the
+    // timeout passes, returning whether it did. Messages stay buffered so
+    // `next` still returns them afterwards.
+    async fn read_until(
+        &mut self,
+        timeout: Duration,
+        mut done: impl FnMut(&IRCMessage) -> bool,
+    ) -> Result<bool, Error> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut scanned = 0;
+
+        loop {
+            while scanned < self.message_buffer.len() {
+                match &self.message_buffer[scanned] {
+                    Ok(IRCMessage::Ping(msg)) if self.auto_pong => {
+                        let msg = msg.clone();
+                        self.message_buffer.remove(scanned);
+                        self.pong(&msg).await?;
+                        continue;
+                    }
+                    Ok(message) if done(message) => return Ok(true),
+                    _ => scanned += 1,
                 }
+            }
 
-                None // Should return Some(Err())
+            if tokio::time::Instant::now() >= deadline {
+                return Ok(false);
+            }
+            if !self.receive(Some(deadline)).await? {
+                return Err(Error::from(ConnectionError::ConnectionClosed));
             }
-            Err(e) => Some(Err(Error::from(e))),
+        }
+    }
+
+    async fn get_next_message(&mut self) -> Option<Result<IRCMessage, Error>> {
+        loop {
+            if let Some(error) = self.background_errors.pop_front() {
+                return Some(Err(error));
+            }
+            if let Some(message) = self.message_buffer.pop_front() {
+                return Some(message.map_err(Error::MessageParseError));
+            }
+
+            match self.receive(None).await {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    // Makes progress on reconnecting, returning the event to report or
+    // None once all attempts have been used up
+    async fn next_reconnect_event(&mut self) -> Option<Result<IRCMessage, Error>> {
+        match self.connection_state {
+            ConnectionState::ReconnectScheduled { attempt } => {
+                if attempt > self.reconnect_policy.max_attempts {
+                    self.transport = None;
+                    self.connection_state = ConnectionState::Closed;
+                    return None;
+                }
+
+                let delay = self.reconnect_policy.delay(attempt);
+                self.connection_state = ConnectionState::ReconnectPending { attempt, delay };
+                Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnecting {
+                    attempt,
+                    delay,
+                })))
+            }
+            ConnectionState::ReconnectPending { attempt, delay } => {
+                tokio::time::sleep(delay).await;
+
+                match self.reconnect().await {
+                    Ok(()) => Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnected))),
+                    Err(e) => {
+                        self.connection_state = ConnectionState::ReconnectScheduled {
+                            attempt: attempt + 1,
+                        };
+                        Some(Err(e))
+                    }
+                }
+            }
+            ConnectionState::Connected | ConnectionState::Closed => None,
         }
     }
 
     pub async fn next(&mut self) -> Option<Result<IRCMessage, Error>> {
         loop {
-            let message = self.get_next_message().await?;
+            match self.connection_state {
+                ConnectionState::Connected => {}
+                ConnectionState::Closed => return None,
+                _ => return self.next_reconnect_event().await,
+            }
+
+            let message = match self.get_next_message().await {
+                // The server is about to restart or the connection was lost
+                Some(Ok(IRCMessage::Reconnect)) | None => {
+                    self.connection_state = ConnectionState::ReconnectScheduled { attempt: 1 };
+                    continue;
+                }
+                Some(Err(e)) if is_connection_lost(&e) => {
+                    self.connection_state = ConnectionState::ReconnectScheduled { attempt: 1 };
+                    continue;
+                }
+                Some(message) => message,
+            };
+
+            match &message {
+                Ok(IRCMessage::Notice { message, .. }) if is_auth_failure(message) => {
+                    self.auth_failed = true;
+                }
+                Ok(IRCMessage::RoomState { channel, state, .. }) => {
+                    self.room_states
+                        .entry(channel.clone())
+                        .or_default()
+                        .update(state);
+                }
+                Ok(IRCMessage::UserState { channel, state, .. }) => {
+                    self.user_states.insert(channel.clone(), state.clone());
+                }
+                Ok(IRCMessage::GlobalUserState { state, .. }) => {
+                    self.global_user_state = Some(state.clone());
+                }
+                _ => {}
+            }
 
             if self.auto_pong {
                 if let Ok(IRCMessage::Ping(msg)) = message {
-                    if let Err(e) = self.pong(msg.as_str()).await {
-                        return Some(Err(e));
+                    match self.pong(msg.as_str()).await {
+                        Err(e) if is_connection_lost(&e) => {
+                            self.connection_state =
+                                ConnectionState::ReconnectScheduled { attempt: 1 };
+                        }
+                        Err(e) => return Some(Err(e)),
+                        Ok(()) => {}
                     }
                     continue;
                 }
@@ -198,4 +1241,875 @@ impl TwitchClient {
             return Some(message);
         }
     }
+
+    /// Receives messages and passes them to the handler until the client
+    /// gives up on the connection. Errors are reported to the handler's
+    /// `on_error` without stopping the client.
+    pub async fn run<H: EventHandler>(&mut self, handler: &mut H) {
+        while let Some(message) = self.next().await {
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    // Without a connection there is nothing left to receive
+                    let not_connected =
+                        matches!(e, Error::ConnectionError(ConnectionError::NotConnected));
+                    handler.on_error(self, HandlerError::from(e)).await;
+                    if not_connected {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            if let Err(e) = event_handler::dispatch(handler, self, &message).await {
+                handler.on_error(self, e).await;
+            }
+        }
+    }
+
+    /// Closes the connection without reconnecting, after which `next`
+    /// returns None. Queued messages and JOINs are sent as far as the rate
+    /// limits allow, the rest is dropped. Revokes the access token if
+    /// enabled with `set_revoke_on_shutdown`.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        // Sending fails if the connection is already gone
+        let _ = self.flush_send_queue().await;
+        let _ = self.flush_join_queue().await;
+        self.send_queue.clear();
+        self.join_queue.clear();
+
+        if let Some(mut transport) = self.transport.take() {
+            // The server may have closed the connection already
+            let _ = transport.close().await;
+        }
+        self.connection_state = ConnectionState::Closed;
+        self.message_buffer.clear();
+
+        let access_token = std::mem::take(&mut self.access_token);
+        self.token_expires_at = None;
+        self.token_refresh_at = None;
+        self.next_token_validation_at = None;
+        self.scopes.send_replace(None);
+
+        match &self.credentials {
+            Some(credentials) if self.revoke_on_shutdown && !access_token.is_empty() => self
+                .auth_client
+                .revoke_token(&credentials.client_id, &access_token)
+                .await
+                .map_err(Error::RevokeTokenError),
+            _ => Ok(()),
+        }
+    }
+
+    /// Turns the client into a `Stream` of the messages returned by `next`
+    pub fn into_stream(self) -> MessageStream {
+        MessageStream::new(self)
+    }
+}
+
+// Only true when the scopes are known, otherwise Twitch has the final say
+fn lacks_scope(scopes: Option<&[Scope]>, scope: &Scope) -> bool {
+    scopes.is_some_and(|s| !s.contains(scope))
+}
+
+fn is_auth_failure(notice: &str) -> bool {
+    notice == "Login authentication failed" || notice == "Improperly formatted auth"
+}
+
+// Whether the connection has to be replaced after the error. Failing to
+// send counts as well, the connection can't be used for chat anymore.
+fn is_connection_lost(error: &Error) -> bool {
+    match error {
+        Error::ConnectionError(
+            ConnectionError::SendMessageFailure(e) | ConnectionError::ReceiveMessageFailure(e),
+        ) => e.is_connection_lost(),
+        _ => false,
+    }
+}
+
+pub(crate) fn anonymous_nick() -> String {
+    format!("justinfan{}", rand::thread_rng().gen_range(1000..100000))
+}
+
+async fn sleep_or_pending(delay: Option<Duration>) {
+    match delay {
+        Some(delay) => tokio::time::sleep(delay).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn sleep_until_or_pending(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::transport::tests::{mock_transport, MockServer};
+
+    fn client() -> TwitchClient {
+        let credentials = Credentials {
+            refresh_token: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+        };
+
+        TwitchClient::new(credentials, "bot".to_owned(), true)
+    }
+
+    // Client connected to a mock transport
+    pub(crate) fn mock_client() -> (TwitchClient, MockServer) {
+        let (transport, server) = mock_transport();
+        let mut client = client();
+        client.transport = Some(Box::new(transport));
+        (client, server)
+    }
+
+    // Queues the connection opened by the next reconnect, which accepts
+    // the login right away
+    fn queue_connection(client: &mut TwitchClient) -> MockServer {
+        let (transport, server) = mock_transport();
+        server.send(":tmi.twitch.tv 001 bot :Welcome, GLHF!\r\n");
+        client.mock_transports.push_back(Box::new(transport));
+        server
+    }
+
+    fn reconnect_policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writer_fails_after_client_dropped() {
+        let client = client();
+        let writer = client.writer();
+
+        // Commands the client didn't get to fail as well
+        let pending = writer.privmsg("xyz", "hello");
+        drop(client);
+        assert!(matches!(
+            pending.await,
+            Err(Error::ConnectionError(ConnectionError::ClientDropped))
+        ));
+        assert!(matches!(
+            writer.privmsg("xyz", "hello").await,
+            Err(Error::ConnectionError(ConnectionError::ClientDropped))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_writer_commands_reach_transport() {
+        let (mut client, mut server) = mock_client();
+        let writer = client.writer();
+        let (messages, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(message) = client.next().await {
+                let _ = messages.send(message);
+            }
+        });
+
+        writer.privmsg("xyz", "hello").await.unwrap();
+        assert_eq!(server.line().await, "PRIVMSG #xyz :hello");
+        writer.join("abc").await.unwrap();
+        assert_eq!(server.line().await, "JOIN #abc");
+        writer.part("abc").await.unwrap();
+        assert_eq!(server.line().await, "PART #abc");
+
+        // A failed command is reported to its writer, not to the reader
+        server.reject_sends();
+        assert!(matches!(
+            writer.part("xyz").await,
+            Err(Error::ConnectionError(ConnectionError::SendMessageFailure(
+                _
+            )))
+        ));
+        server.send(":tmi.twitch.tv NOTICE * :a\r\n");
+        assert!(matches!(
+            received.recv().await,
+            Some(Ok(IRCMessage::Notice { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_writer_is_send_and_clone() {
+        fn assert_send_clone<T: Send + Clone>() {}
+        assert_send_clone::<TwitchWriter>();
+    }
+
+    #[tokio::test]
+    async fn test_plain_tcp_server() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let pass = lines.next_line().await.unwrap().unwrap();
+            let nick = lines.next_line().await.unwrap().unwrap();
+
+            writer
+                .write_all(b":tmi.twitch.tv 001 bot :Welcome, GLHF!\r\nPING :tmi.twitch.tv\r\n")
+                .await
+                .unwrap();
+            let pong = lines.next_line().await.unwrap().unwrap();
+            writer
+                .write_all(b":tmi.twitch.tv NOTICE * :Pong received\r\n")
+                .await
+                .unwrap();
+            (pass, nick, pong)
+        });
+
+        let mut client = client();
+        client.set_url(Url::parse(&format!("irc://127.0.0.1:{port}")).unwrap());
+        client.connect().await.unwrap();
+        client.authenticate().await.unwrap();
+
+        let message = client.next().await.unwrap().unwrap();
+        assert!(matches!(message, IRCMessage::Numbered { number: 1, .. }));
+        // The PING is answered without being returned
+        let message = client.next().await.unwrap().unwrap();
+        assert!(matches!(message, IRCMessage::Notice { .. }));
+
+        let (pass, nick, pong) = server.await.unwrap();
+        assert_eq!(pass, "PASS oauth:");
+        assert_eq!(nick, "NICK bot");
+        assert_eq!(pong, "PONG :tmi.twitch.tv");
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_client_is_read_only() {
+        let mut client = TwitchClient::anonymous(true);
+
+        assert!(client.is_anonymous());
+        assert!(client.nick_name().starts_with("justinfan"));
+        assert!(client.update_access_token().await.is_ok());
+        assert!(matches!(
+            client.privmsg("xyz", "hello").await,
+            Err(Error::AnonymousReadOnly)
+        ));
+        assert!(matches!(
+            client.writer().privmsg("xyz", "hello").await,
+            Err(Error::AnonymousReadOnly)
+        ));
+        assert_eq!(client.send_queue_len(), 0);
+    }
+
+    // Accepts one connection, reads PASS and NICK, answers with the reply
+    // and keeps the connection open until the client closes it
+    async fn login_server(reply: &'static [u8]) -> Url {
+        login_servers(&[reply]).await.0
+    }
+
+    // Like login_server, but accepts one connection per reply in order and
+    // returns the PASS line of each
+    async fn login_servers(
+        replies: &[&'static [u8]],
+    ) -> (Url, tokio::task::JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("irc://{}", listener.local_addr().unwrap())).unwrap();
+        let replies = replies.to_vec();
+
+        let server = tokio::spawn(async move {
+            let mut passes = Vec::new();
+            for reply in replies {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                passes.push(lines.next_line().await.unwrap().unwrap());
+                lines.next_line().await.unwrap();
+
+                writer.write_all(reply).await.unwrap();
+                tokio::spawn(async move {
+                    let _writer = writer;
+                    while let Ok(Some(_)) = lines.next_line().await {}
+                });
+            }
+            passes
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_result() {
+        let mut rejected = client();
+        rejected.set_url(
+            login_server(b":tmi.twitch.tv NOTICE * :Login authentication failed\r\n").await,
+        );
+        rejected.connect().await.unwrap();
+        assert!(matches!(
+            rejected.authenticate().await,
+            Err(Error::AuthenticationFailed(message)) if message == "Login authentication failed"
+        ));
+
+        let mut silent = client();
+        silent.set_auth_timeout(Duration::from_millis(50));
+        silent.set_url(login_server(b"").await);
+        silent.connect().await.unwrap();
+        assert!(matches!(
+            silent.authenticate().await,
+            Err(Error::AuthenticationTimeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_login_is_retried_with_new_token() {
+        let (auth_url, token_server) = auth::tests::http_stand_in(
+            "200 OK",
+            r#"{"access_token":"access","expires_in":3600,"refresh_token":"refresh",
+            "scope":["chat:read"],"token_type":"bearer"}"#,
+        )
+        .await;
+        let (url, login_servers) = login_servers(&[
+            b":tmi.twitch.tv NOTICE * :Login authentication failed\r\n",
+            b":tmi.twitch.tv 001 bot :Welcome, GLHF!\r\n",
+        ])
+        .await;
+
+        let mut client = client();
+        client.set_auth_url(auth_url);
+        client.set_url(url);
+        client.set_refresh_on_auth_failure(true);
+        client.connect().await.unwrap();
+        client.authenticate().await.unwrap();
+
+        assert!(token_server
+            .await
+            .unwrap()
+            .contains("grant_type=refresh_token"));
+        assert_eq!(
+            login_servers.await.unwrap(),
+            vec!["PASS oauth:", "PASS oauth:access"]
+        );
+        assert!(!client.auth_failed);
+    }
+
+    #[test]
+    fn test_apply_refreshed_token() {
+        use std::sync::{Arc, Mutex};
+
+        let saved = Arc::new(Mutex::new(Vec::new()));
+        let mut client = client();
+        let callback_saved = saved.clone();
+        client.set_token_refresh_callback(move |credentials| {
+            callback_saved
+                .lock()
+                .unwrap()
+                .push(credentials.refresh_token.clone());
+        });
+
+        let response = |refresh_token: &str| auth::RefreshTokenResponse {
+            access_token: "access".to_owned(),
+            expires_in: 3600,
+            refresh_token: refresh_token.to_owned(),
+            scope: Vec::new(),
+            token_type: "bearer".to_owned(),
+        };
+        let now = Instant::now();
+
+        client
+            .apply_refreshed_token(response("rotated"), now)
+            .unwrap();
+        client
+            .apply_refreshed_token(response("rotated"), now)
+            .unwrap();
+        assert_eq!(client.access_token, "access");
+        assert_eq!(client.credentials().unwrap().refresh_token, "rotated");
+        assert_eq!(*saved.lock().unwrap(), vec!["rotated"]);
+
+        assert_eq!(
+            client.token_expires_at(),
+            Some(now + Duration::from_secs(3600))
+        );
+        assert_eq!(
+            client.token_refresh_at,
+            Some(now + Duration::from_secs(3300))
+        );
+        assert!(!client.token_expired(now));
+        assert!(client.token_expired(now + Duration::from_secs(3600)));
+
+        let expired = auth::RefreshTokenResponse {
+            expires_in: 0,
+            ..response("rotated")
+        };
+        client.apply_refreshed_token(expired, now).unwrap();
+        assert_eq!(client.token_refresh_at, Some(now + MIN_TOKEN_REFRESH_DELAY));
+    }
+
+    #[test]
+    fn test_token_store_is_used() {
+        use crate::token_store::MemoryTokenStore;
+
+        let store = MemoryTokenStore::new(Some(StoredToken {
+            refresh_token: "stored".to_owned(),
+        }));
+        let mut client = client();
+        client.set_token_store(store.clone());
+
+        client.load_stored_token().unwrap();
+        assert_eq!(client.credentials().unwrap().refresh_token, "stored");
+
+        let response = auth::RefreshTokenResponse {
+            access_token: "access".to_owned(),
+            expires_in: 3600,
+            refresh_token: "rotated".to_owned(),
+            scope: Vec::new(),
+            token_type: "bearer".to_owned(),
+        };
+        client
+            .apply_refreshed_token(response, Instant::now())
+            .unwrap();
+        assert_eq!(store.token().unwrap().refresh_token, "rotated");
+    }
+
+    #[tokio::test]
+    async fn test_unsaved_token_is_not_replaced_by_stored_one() {
+        struct ReadOnlyStore;
+
+        impl TokenStore for ReadOnlyStore {
+            fn load(&mut self) -> Result<Option<StoredToken>, TokenStoreError> {
+                Ok(Some(StoredToken {
+                    refresh_token: "stored".to_owned(),
+                }))
+            }
+
+            fn save(&mut self, _: &StoredToken) -> Result<(), TokenStoreError> {
+                let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+                Err(TokenStoreError::Io(error))
+            }
+        }
+
+        let response = r#"{"access_token":"access","expires_in":3600,"refresh_token":"rotated",
+            "scope":[],"token_type":"bearer"}"#;
+        let (url, server) =
+            auth::tests::http_stand_in_sequence(&[("200 OK", response), ("200 OK", response)])
+                .await;
+        let mut client = client();
+        client.set_auth_url(url);
+        client.set_token_store(ReadOnlyStore);
+
+        assert!(matches!(
+            client.update_access_token().await,
+            Err(Error::TokenStoreError(_))
+        ));
+        client.update_access_token().await.unwrap();
+        let requests = server.await.unwrap();
+
+        assert!(requests[0].contains("refresh_token=stored"));
+        assert!(requests[1].contains("refresh_token=rotated"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_access_token() {
+        let (url, _) = auth::tests::http_stand_in(
+            "200 OK",
+            r#"{"client_id":"id","login":"bot","scopes":null,"user_id":"1","expires_in":3600}"#,
+        )
+        .await;
+        let mut client = client();
+        client.set_auth_url(url);
+
+        let validation = client.validate_access_token().await.unwrap();
+        assert_eq!(validation.login.as_deref(), Some("bot"));
+        assert!(validation.scopes.is_empty());
+        assert!(client.token_validation().is_some());
+        assert!(client.token_expires_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_revokes_token() {
+        let (url, server) = auth::tests::http_stand_in("200 OK", "").await;
+        let mut client = client();
+        client.set_auth_url(url);
+        client.set_revoke_on_shutdown(true);
+        client.access_token = "abc".to_owned();
+
+        client.shutdown().await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.starts_with("POST /revoke "));
+        assert!(request.contains("token=abc"));
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_missing_scope_fails_before_sending() {
+        let mut client = client();
+        let writer = client.writer();
+        let response = auth::RefreshTokenResponse {
+            access_token: "access".to_owned(),
+            expires_in: 3600,
+            refresh_token: String::new(),
+            scope: vec!["chat:read".to_owned()],
+            token_type: "bearer".to_owned(),
+        };
+        client
+            .apply_refreshed_token(response, Instant::now())
+            .unwrap();
+
+        assert_eq!(client.scopes(), Some(vec![Scope::ChatRead]));
+        assert!(matches!(
+            client.privmsg("xyz", "hello").await,
+            Err(Error::MissingScope(Scope::ChatEdit))
+        ));
+        assert_eq!(client.send_queue_len(), 0);
+
+        // The writer sees the scopes reported after it was created
+        assert!(matches!(
+            writer.privmsg("xyz", "hello").await,
+            Err(Error::MissingScope(Scope::ChatEdit))
+        ));
+
+        // Not connected, so the JOIN passes the scope check but can't be sent
+        assert!(matches!(
+            client.join("xyz").await,
+            Err(Error::ConnectionError(ConnectionError::NotConnected))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_server_reconnect_restores_session() {
+        let (mut client, mut server) = mock_client();
+        client.set_reconnect_policy(reconnect_policy());
+        client.cap_req(&[Capability::Tags]).await.unwrap();
+        client.join("xyz").await.unwrap();
+        assert_eq!(server.lines(), vec!["CAP REQ :twitch.tv/tags", "JOIN #xyz"]);
+
+        let mut new_server = queue_connection(&mut client);
+        server.send(":tmi.twitch.tv RECONNECT\r\n");
+
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnecting {
+                attempt: 1,
+                ..
+            })))
+        ));
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnected)))
+        ));
+        // The welcome message the reconnect waited for comes next
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Numbered { number: 1, .. }))
+        ));
+        assert_eq!(
+            new_server.lines(),
+            vec![
+                "PASS oauth:",
+                "NICK bot",
+                "CAP REQ :twitch.tv/tags",
+                "JOIN #xyz"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queued_messages_wait_for_restored_session() {
+        let (mut client, server) = mock_client();
+        client.set_reconnect_policy(ReconnectPolicy {
+            base_delay: Duration::ZERO,
+            ..reconnect_policy()
+        });
+        client.join("xyz").await.unwrap();
+
+        let mut new_server = queue_connection(&mut client);
+        server.send(":tmi.twitch.tv RECONNECT\r\n");
+        assert!(client.next().await.unwrap().is_ok());
+        client
+            .send_queue
+            .push_back(("xyz".to_owned(), "queued".to_owned()));
+        // Reconnected, then the welcome message the login waited for
+        for _ in 0..2 {
+            assert!(client.next().await.unwrap().is_ok());
+        }
+        assert_eq!(
+            new_server.lines(),
+            vec!["PASS oauth:", "NICK bot", "JOIN #xyz"]
+        );
+
+        // Sent once the client receives on the restored connection
+        let next = tokio::time::timeout(Duration::from_millis(50), client.next()).await;
+        assert!(next.is_err());
+        assert_eq!(new_server.lines(), vec!["PRIVMSG #xyz :queued"]);
+        assert_eq!(client.send_queue_len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_failure_reconnects_and_rejoins() {
+        let (mut client, server) = mock_client();
+        client.set_reconnect_policy(reconnect_policy());
+        client.join("xyz").await.unwrap();
+
+        let mut new_server = queue_connection(&mut client);
+        server.fail(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into());
+
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Lifecycle(
+                LifecycleEvent::Reconnecting { .. }
+            )))
+        ));
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnected)))
+        ));
+        assert_eq!(
+            new_server.lines(),
+            vec!["PASS oauth:", "NICK bot", "JOIN #xyz"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_only_lost_connections_reconnect() {
+        let (mut client, mut server) = mock_client();
+        client.set_reconnect_policy(reconnect_policy());
+
+        // Invalid text only loses that text
+        server.fail(TransportError::InvalidUtf8);
+        assert!(matches!(
+            client.next().await,
+            Some(Err(Error::ConnectionError(
+                ConnectionError::ReceiveMessageFailure(TransportError::InvalidUtf8)
+            )))
+        ));
+        server.send(":tmi.twitch.tv NOTICE * :still here\r\n");
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Notice { .. }))
+        ));
+
+        // Failing to answer a PING means the connection is gone
+        queue_connection(&mut client);
+        server.reject_sends();
+        server.send("PING :tmi.twitch.tv\r\n");
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Lifecycle(
+                LifecycleEvent::Reconnecting { .. }
+            )))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnect_gives_up_after_max_attempts() {
+        let (mut client, server) = mock_client();
+        client.set_reconnect_policy(reconnect_policy());
+        // Connections that are closed before the login is sent
+        for _ in 0..2 {
+            drop(queue_connection(&mut client));
+        }
+        server.send(":tmi.twitch.tv RECONNECT\r\n");
+
+        for attempt in 1..=2 {
+            assert!(matches!(
+                client.next().await,
+                Some(Ok(IRCMessage::Lifecycle(LifecycleEvent::Reconnecting { attempt: a, .. })))
+                    if a == attempt
+            ));
+            assert!(matches!(
+                client.next().await,
+                Some(Err(Error::ConnectionError(
+                    ConnectionError::SendMessageFailure(_)
+                )))
+            ));
+        }
+        assert!(client.next().await.is_none());
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_privmsg_is_queued_behind_rate_limit() {
+        use crate::rate_limit::Limit;
+
+        let (mut client, mut server) = mock_client();
+        let limit = Limit {
+            messages: 2,
+            period: Duration::from_millis(200),
+        };
+        client.set_rate_limits(RateLimits {
+            normal: limit,
+            moderator: limit,
+            ..RateLimits::default()
+        });
+
+        for message in ["a", "b", "c"] {
+            client.privmsg("xyz", message).await.unwrap();
+        }
+        assert_eq!(server.lines(), vec!["PRIVMSG #xyz :a", "PRIVMSG #xyz :b"]);
+        assert_eq!(client.send_queue_len(), 1);
+
+        // Both sends leave the window 200ms after they were made
+        let delay = client.queue_delay().unwrap();
+        assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        let wait_time = client.send_wait_time("xyz");
+        assert!(wait_time > Duration::from_millis(100) && wait_time <= Duration::from_millis(200));
+
+        // The queued message goes out while next is waiting
+        let next = tokio::time::timeout(Duration::from_millis(250), client.next()).await;
+        assert!(next.is_err());
+        assert_eq!(server.lines(), vec!["PRIVMSG #xyz :c"]);
+        assert_eq!(client.send_queue_len(), 0);
+        assert_eq!(client.queue_delay(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_many_report() {
+        let (mut client, mut server) = mock_client();
+        server.send(
+            ":bot!bot@bot.tmi.twitch.tv JOIN #a\r\n\
+            @msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #b :This channel does not exist or has been suspended.\r\n\
+            @msg-id=slow_on :tmi.twitch.tv NOTICE #c :This room is now in slow mode.\r\n",
+        );
+
+        let report = client.join_many(&["a", "b", "c"]).await.unwrap();
+        assert_eq!(server.lines(), vec!["JOIN #a,#b,#c"]);
+        assert_eq!(report.joined, vec!["a"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "b");
+        // The slow mode notice isn't about joining
+        assert_eq!(report.unconfirmed, vec!["c"]);
+        assert_eq!(client.joined_channels, vec!["a", "c"]);
+
+        // The messages are still returned by next
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(IRCMessage::Join { .. }))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_lines_fit_line_length() {
+        let (mut client, mut server) = mock_client();
+        let channel_names = (0..19).map(|i| format!("{i:0>25}")).collect::<Vec<_>>();
+        let channel_names = channel_names.iter().map(String::as_str).collect::<Vec<_>>();
+
+        client.send_joins(&channel_names).await.unwrap();
+        let lines = server.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+
+        let joined = lines
+            .iter()
+            .flat_map(|line| line.trim_start_matches("JOIN ").split(','))
+            .collect::<Vec<_>>();
+        let expected = channel_names
+            .iter()
+            .map(|c| format!("#{c}"))
+            .collect::<Vec<_>>();
+        assert_eq!(joined, expected);
+    }
+
+    #[tokio::test]
+    async fn test_joins_are_split_by_rate_limit() {
+        use crate::rate_limit::Limit;
+
+        let (mut client, mut server) = mock_client();
+        client.set_rate_limits(RateLimits {
+            joins: Limit {
+                messages: 2,
+                period: Duration::from_millis(100),
+            },
+            ..RateLimits::default()
+        });
+        client.set_join_timeout(Duration::from_millis(10));
+
+        let report = client.join_many(&["a", "b", "c"]).await.unwrap();
+        assert_eq!(server.lines(), vec!["JOIN #a,#b", "JOIN #c"]);
+        assert_eq!(report.unconfirmed, vec!["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_validation_does_not_abort_join() {
+        let (url, _) = auth::tests::http_stand_in("500 Internal Server Error", "").await;
+        let (mut client, mut server) = mock_client();
+        client.set_auth_url(url);
+        client.set_token_validation_interval(Some(Duration::from_millis(10)));
+        client.set_join_timeout(Duration::from_millis(100));
+
+        let report = client.join_many(&["xyz"]).await.unwrap();
+        assert_eq!(server.lines(), vec!["JOIN #xyz"]);
+        assert_eq!(report.unconfirmed, vec!["xyz"]);
+
+        // The failure is reported by next instead
+        assert!(matches!(
+            client.next().await,
+            Some(Err(Error::ValidateTokenError(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_queued_joins_count_towards_join_limit() {
+        use crate::rate_limit::Limit;
+
+        let (mut client, mut server) = mock_client();
+        client.set_rate_limits(RateLimits {
+            joins: Limit {
+                messages: 1,
+                period: Duration::from_millis(100),
+            },
+            ..RateLimits::default()
+        });
+        client.join_queue.push_back("queued".to_owned());
+
+        let started = Instant::now();
+        client.send_joins(&["a", "b"]).await.unwrap();
+        let lines = server.lines();
+
+        // The queued JOIN may go out while waiting, but never on top of "b"
+        assert!(lines.contains(&"JOIN #b".to_owned()));
+        let sends = u32::try_from(lines.len()).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100) * (sends - 1));
+    }
+
+    #[tokio::test]
+    async fn test_failed_joins_stay_queued() {
+        let (mut client, mut server) = mock_client();
+        client.join_queue.push_back("xyz".to_owned());
+        server.reject_sends();
+
+        assert!(client.flush_join_queue().await.is_err());
+        assert_eq!(client.join_queue, vec!["xyz"]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_queues() {
+        use crate::rate_limit::Limit;
+
+        let (mut client, mut server) = mock_client();
+        let limit = Limit {
+            messages: 1,
+            period: Duration::from_secs(30),
+        };
+        client.set_rate_limits(RateLimits {
+            normal: limit,
+            moderator: limit,
+            joins: limit,
+        });
+        for message in ["a", "b"] {
+            client
+                .send_queue
+                .push_back(("xyz".to_owned(), message.to_owned()));
+        }
+        client
+            .join_queue
+            .extend(["abc".to_owned(), "def".to_owned()]);
+
+        client.shutdown().await.unwrap();
+        assert_eq!(server.lines(), vec!["PRIVMSG #xyz :a", "JOIN #abc"]);
+        assert_eq!(client.send_queue_len(), 0);
+        assert!(client.join_queue.is_empty());
+    }
 }